@@ -0,0 +1,184 @@
+use crate::get_env_typed;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use ring::digest::{digest, SHA256};
+use std::fs;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+// RFC 7231 IMF-fixdate
+const HTTP_DATE: &str = "%a, %d %b %Y %H:%M:%S GMT";
+
+lazy_static! {
+    static ref MAX_AGE: u64 = get_env_typed("THUMB_CACHE_MAX_AGE", 86400);
+    static ref PRIVATE_MAX_AGE: u64 = get_env_typed("THUMB_CACHE_PRIVATE_MAX_AGE", 3600);
+}
+
+/// What a client or CDN needs to revalidate a cached thumbnail
+#[derive(Debug, Clone, PartialEq)]
+pub struct Validators {
+    pub etag: String,
+    /// Seconds since the epoch
+    pub last_modified: Option<u64>,
+    /// Thumbnails from hidden folders must stay out of shared caches
+    pub private: bool,
+}
+
+impl Validators {
+    /// A strong validator derived from the name of the file being served and
+    /// when it was written. Image thumbnails are named after their original,
+    /// size, orientation and format, and a folder thumbnail written afresh
+    /// gets a new time, so neither needs the bytes hashed.
+    pub fn new(name: &str, last_modified: Option<u64>, private: bool) -> Self {
+        let key = format!("{name}\0{}", last_modified.unwrap_or_default());
+        let hash = digest(&SHA256, key.as_bytes());
+        Self {
+            etag: format!("\"{}\"", hex::encode(&hash.as_ref()[..16])),
+            last_modified,
+            private,
+        }
+    }
+
+    /// Validators for a thumbnail on disk, from its metadata alone
+    pub fn for_file(path: &Path, private: bool) -> Self {
+        let last_modified = fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| since.as_secs());
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        Self::new(&name, last_modified, private)
+    }
+
+    fn cache_control(&self) -> String {
+        if self.private {
+            format!("private, max-age={}", *PRIVATE_MAX_AGE)
+        } else {
+            format!("public, max-age={}", *MAX_AGE)
+        }
+    }
+
+    fn last_modified_date(&self) -> Option<String> {
+        let secs = i64::try_from(self.last_modified?).ok()?;
+        DateTime::<Utc>::from_timestamp(secs, 0).map(|d| d.format(HTTP_DATE).to_string())
+    }
+
+    /// Whether the client's copy is still current. `If-None-Match` wins over
+    /// `If-Modified-Since` when both are sent.
+    pub fn is_fresh(&self, headers: &HeaderMap) -> bool {
+        if let Some(if_none_match) = headers
+            .get(header::IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+        {
+            return if_none_match
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == self.etag);
+        }
+
+        let since = headers
+            .get(header::IF_MODIFIED_SINCE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| NaiveDateTime::parse_from_str(v, HTTP_DATE).ok())
+            .map(|d| d.and_utc().timestamp());
+        match (since, self.last_modified) {
+            (Some(since), Some(modified)) => i64::try_from(modified).is_ok_and(|m| m <= since),
+            _ => false,
+        }
+    }
+
+    fn headers(&self) -> Vec<(header::HeaderName, HeaderValue)> {
+        let mut headers = vec![(header::CACHE_CONTROL, self.cache_control())];
+        headers.push((header::ETAG, self.etag.clone()));
+        if let Some(date) = self.last_modified_date() {
+            headers.push((header::LAST_MODIFIED, date));
+        }
+        headers
+            .into_iter()
+            .filter_map(|(name, value)| HeaderValue::from_str(&value).ok().map(|v| (name, v)))
+            .collect()
+    }
+}
+
+/// The thumbnail with caching headers, or an empty 304 when the client's copy
+/// is still current
+pub fn cached_response(
+    request_headers: &HeaderMap,
+    validators: Validators,
+    content_type: &'static str,
+    data: Vec<u8>,
+) -> Response {
+    let mut response = if validators.is_fresh(request_headers) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        (
+            StatusCode::OK,
+            [(header::CONTENT_TYPE, HeaderValue::from_static(content_type))],
+            data,
+        )
+            .into_response()
+    };
+
+    let headers = response.headers_mut();
+    headers.insert(header::VARY, HeaderValue::from_static("Accept"));
+    for (name, value) in validators.headers() {
+        headers.insert(name, value);
+    }
+    response
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn request(name: header::HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    pub fn it_matches_etags() {
+        let validators = Validators::new("a.jpg-300-1a2b3c4d.webp", None, false);
+        let etag = validators.etag.clone();
+        assert!(validators.is_fresh(&request(header::IF_NONE_MATCH, &etag)));
+        assert!(validators.is_fresh(&request(
+            header::IF_NONE_MATCH,
+            &format!("\"other\", W/{etag}")
+        )));
+        assert!(!validators.is_fresh(&request(header::IF_NONE_MATCH, "\"other\"")));
+        assert!(!validators.is_fresh(&HeaderMap::new()));
+    }
+
+    #[test]
+    pub fn it_changes_etags_with_the_file_served() {
+        let etag = |name, modified| Validators::new(name, Some(modified), false).etag;
+        assert_eq!(etag("thumb-300", 1), etag("thumb-300", 1));
+        assert_ne!(etag("thumb-300", 1), etag("thumb-300", 2));
+        assert_ne!(etag("thumb-300", 1), etag("thumb-300.avif", 1));
+    }
+
+    #[test]
+    pub fn it_compares_modification_dates() {
+        let validators = Validators::new("a.jpg-300-1a2b3c4d.webp", Some(1_700_000_000), false);
+        let date = validators.last_modified_date().unwrap();
+        assert_eq!(date, "Tue, 14 Nov 2023 22:13:20 GMT");
+        assert!(validators.is_fresh(&request(header::IF_MODIFIED_SINCE, &date)));
+        assert!(!validators.is_fresh(&request(
+            header::IF_MODIFIED_SINCE,
+            "Tue, 14 Nov 2023 22:13:19 GMT"
+        )));
+    }
+
+    #[test]
+    pub fn it_answers_304_with_private_caching_for_hidden_folders() {
+        let validators = Validators::new("a.jpg-300-1a2b3c4d.webp", None, true);
+        let headers = request(header::IF_NONE_MATCH, &validators.etag);
+        let response = cached_response(&headers, validators, "image/webp", b"thumb".to_vec());
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+        let cache_control = response.headers()[header::CACHE_CONTROL].to_str().unwrap();
+        assert!(cache_control.starts_with("private"));
+    }
+}
@@ -1,7 +1,12 @@
+use crate::api::cache::{cached_response, Validators};
 use crate::context::GraphQLContext;
+use crate::format::ThumbFormat;
 use crate::pgp::AuthName;
+use crate::thumbnail::resolve_size;
+use axum::body::Body;
 use axum::extract::{FromRequestParts, Query};
 use axum::http::request::Parts;
+use axum::http::{header, HeaderMap, HeaderValue, Request};
 use axum::response::{IntoResponse, Response};
 use axum::routing::get;
 use axum::RequestPartsExt;
@@ -12,11 +17,13 @@ use serde::{de, Deserialize, Deserializer, Serialize};
 use std::fmt;
 use std::str::FromStr;
 use std::sync::Arc;
-use tower::ServiceBuilder;
+use tower::{ServiceBuilder, ServiceExt};
+use tower_http::services::ServeFile;
 use tower_http::ServiceBuilderExt;
 
 use axum::extract::Path;
 
+pub mod cache;
 pub mod login;
 
 pub fn middleware() -> tower::ServiceBuilder<
@@ -35,54 +42,224 @@ pub fn api_routes(context: Arc<GraphQLContext>) -> Router {
         .route("/test", get(get_test))
         .route("/folderThumb/:size/:folder", get(folder_thumbnail))
         .route("/imageThumb/:size/:image", get(image_thumbnail))
+        .route("/preview/:image", get(image_preview))
+        .route("/exif/:image", get(image_exif))
+        .route("/original/:image", get(original_download))
+        .route("/download/:folder", get(folder_download))
+        .route(
+            "/admin/thumbnails",
+            get(thumbnail_progress).post(start_thumbnails),
+        )
         // .nest("/vote", voting_routes(context.clone()))
         .nest("/login", login_routes(context.clone()))
         .layer(Extension(context.clone()))
 }
 
+/// Thumbnail encoding for the request's `Accept` header
+fn thumb_format(headers: &HeaderMap) -> ThumbFormat {
+    let accept = headers
+        .get(header::ACCEPT)
+        .and_then(|value| value.to_str().ok());
+    ThumbFormat::from_accept(accept)
+}
+
 pub async fn folder_thumbnail(
     Path((size, folder)): Path<(u32, String)>,
+    headers: HeaderMap,
     SessionContext(context): SessionContext,
 ) -> Response {
-    use http::header;
-
     use crate::image::ImageSvc;
 
+    let Some(size) = resolve_size(size) else {
+        return (StatusCode::BAD_REQUEST, "thumbnail size not allowed").into_response();
+    };
+    let format = thumb_format(&headers);
     trace!("Auth for folder thumb is: {:?}", context.auth);
-    let result = ImageSvc::get_folder_thumbnail(&context, &folder, size).await;
+    let result = ImageSvc::get_folder_thumbnail(&context, &folder, size, format).await;
     match result {
         Err(e) => {
-            error!("Error retrieving thumbnail: {:?}", e);
-            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+            trace!("Error retrieving thumbnail: {:?}", e);
+            e.into_response()
+        }
+        Ok(thumb) => {
+            // the cover may change while the folder does not, so go by the file served
+            let validators =
+                Validators::for_file(&thumb.path, ImageSvc::is_hidden(&folder, &None).await);
+            cached_response(&headers, validators, format.content_type(), thumb.data)
         }
-        Ok(data) => (
-            StatusCode::OK,
-            axum::response::AppendHeaders([(header::CONTENT_TYPE, "image/webp")]),
-            data,
-        )
-            .into_response(),
     }
 }
 pub async fn image_thumbnail(
     Path((size, image)): Path<(u32, String)>,
+    headers: HeaderMap,
     SessionContext(context): SessionContext,
 ) -> Response {
-    use http::header;
+    use crate::image::ImageSvc;
 
+    let Some(size) = resolve_size(size) else {
+        return (StatusCode::BAD_REQUEST, "thumbnail size not allowed").into_response();
+    };
+    let format = thumb_format(&headers);
+    let result = ImageSvc::thumbnail(&context, &image, size, format).await;
+    match result {
+        Err(e) => e.into_response(),
+        Ok(thumb) => {
+            let folder = image.rsplit_once('/').map_or("/", |(folder, _)| folder);
+            let validators =
+                Validators::for_file(&thumb.path, ImageSvc::is_hidden(folder, &None).await);
+            cached_response(&headers, validators, format.content_type(), thumb.data)
+        }
+    }
+}
+
+pub async fn image_preview(
+    Path(image): Path<String>,
+    SessionContext(context): SessionContext,
+) -> Response {
     use crate::image::ImageSvc;
 
-    let result = ImageSvc::thumbnail(&context, &image, size).await;
+    let result = ImageSvc::preview(&context, &image).await;
     match result {
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR).into_response(),
+        Err(e) => e.into_response(),
         Ok(data) => (
             StatusCode::OK,
-            axum::response::AppendHeaders([(header::CONTENT_TYPE, "image/webp")]),
+            axum::response::AppendHeaders([(header::CONTENT_TYPE, "image/jpeg")]),
             data,
         )
             .into_response(),
     }
 }
 
+pub async fn image_exif(
+    Path(image): Path<String>,
+    SessionContext(context): SessionContext,
+) -> Response {
+    use crate::image::ImageSvc;
+
+    let result = ImageSvc::exif(&context, &image).await;
+    match result {
+        Err(e) => e.into_response(),
+        Ok(data) => (StatusCode::OK, Json(data)).into_response(),
+    }
+}
+
+/// Stream a full-resolution original; `ServeFile` takes care of `Range` and
+/// conditional requests
+pub async fn original_download(
+    Path(image): Path<String>,
+    SessionContext(context): SessionContext,
+    request: Request<Body>,
+) -> Response {
+    use crate::image::ImageSvc;
+
+    let original = match ImageSvc::original(&context, &image).await {
+        Ok(original) => original,
+        Err(e) => return e.into_response(),
+    };
+
+    let name = original
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let disposition = format!(
+        "attachment; filename=\"{}\"; filename*=UTF-8''{}",
+        name.replace(['"', '\\'], "_"),
+        urlencoding::encode(&name)
+    );
+
+    match ServeFile::new(original).oneshot(request).await {
+        Ok(response) => {
+            let mut response = response.map(Body::new).into_response();
+            if let Ok(value) = HeaderValue::from_str(&disposition) {
+                response
+                    .headers_mut()
+                    .insert(header::CONTENT_DISPOSITION, value);
+            }
+            response
+        }
+        Err(e) => {
+            error!("Error serving original {image}: {:?}", e);
+            (StatusCode::INTERNAL_SERVER_ERROR).into_response()
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct DownloadParameters {
+    #[serde(default)]
+    recursive: bool,
+}
+
+/// Stream every visible image of a folder as `<folder>.zip`
+pub async fn folder_download(
+    Path(folder): Path<String>,
+    Query(params): Query<DownloadParameters>,
+    SessionContext(context): SessionContext,
+) -> Response {
+    use crate::archive::zip_stream;
+    use crate::image::ImageSvc;
+
+    let Some(folder) = folder.strip_suffix(".zip") else {
+        return (StatusCode::NOT_FOUND).into_response();
+    };
+
+    let entries = match ImageSvc::archive_entries(&context, folder, params.recursive).await {
+        Ok(entries) => entries,
+        Err(e) => return e.into_response(),
+    };
+
+    let name = folder
+        .rsplit('/')
+        .find(|part| !part.is_empty())
+        .unwrap_or("photos");
+    let disposition = format!(
+        "attachment; filename=\"{}.zip\"; filename*=UTF-8''{}.zip",
+        name.replace(['"', '\\'], "_"),
+        urlencoding::encode(name)
+    );
+
+    let mut response = (
+        StatusCode::OK,
+        axum::response::AppendHeaders([(header::CONTENT_TYPE, "application/zip")]),
+        Body::from_stream(zip_stream(entries)),
+    )
+        .into_response();
+    if let Ok(value) = HeaderValue::from_str(&disposition) {
+        response
+            .headers_mut()
+            .insert(header::CONTENT_DISPOSITION, value);
+    }
+    response
+}
+
+fn is_admin(context: &GraphQLContext) -> bool {
+    context
+        .auth
+        .as_ref()
+        .is_some_and(|auth| auth.name == "super")
+}
+
+/// Progress of the background thumbnail pre-generation
+pub async fn thumbnail_progress(SessionContext(context): SessionContext) -> Response {
+    if !is_admin(&context) {
+        return (StatusCode::FORBIDDEN).into_response();
+    }
+    (StatusCode::OK, Json(context.thumbnail_worker.progress())).into_response()
+}
+
+/// Kick off a pre-generation run; 409 if one is already going
+pub async fn start_thumbnails(SessionContext(context): SessionContext) -> Response {
+    if !is_admin(&context) {
+        return (StatusCode::FORBIDDEN).into_response();
+    }
+    let status = if context.thumbnail_worker.start(context.clone()) {
+        StatusCode::ACCEPTED
+    } else {
+        StatusCode::CONFLICT
+    };
+    (status, Json(context.thumbnail_worker.progress())).into_response()
+}
+
 pub async fn get_test() -> &'static str {
     " hello world"
 }
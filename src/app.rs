@@ -6,12 +6,17 @@ use leptos::*;
 use leptos_meta::*;
 use leptos_router::*;
 use leptos_use::*;
+use search::SearchBox;
+use slideshow::{Slideshow, SLIDESHOW_PARAM};
 
 mod breadcrumb_nav;
 mod folder_list;
 mod folder_thumb;
 mod image_list;
 mod image_thumb;
+mod lightbox;
+mod search;
+mod slideshow;
 
 #[component]
 pub fn App() -> impl IntoView {
@@ -31,19 +36,32 @@ pub fn App() -> impl IntoView {
             view! {
                 // let mut outside_errors = Errors::default();
                 // outside_errors.insert_with_default_key(AppError::NotFound);
-                <HomePage />
+                <FolderPage />
             }
                 .into_view()
         }>
             <main>
                 <Routes>
-                    <Route path="/" view=HomePage />
+                    <Route path="/" view=FolderPage />
                 </Routes>
             </main>
         </Router>
     }
 }
 
+/// A folder, or its slideshow when the query asks for one
+#[component]
+fn FolderPage() -> impl IntoView {
+    let query = use_query_map();
+    let slideshow = move || query.with(|q| q.get(SLIDESHOW_PARAM).is_some());
+
+    view! {
+        <Show when=slideshow fallback=|| view! { <HomePage /> }>
+            <Slideshow />
+        </Show>
+    }
+}
+
 #[derive(Debug, Params, PartialEq)]
 struct AuthParams {
     auth: Option<String>,
@@ -69,18 +87,14 @@ fn HomePage() -> impl IntoView {
             set_auth(auth);
         }
     });
-    // Creates a reactive value to update the button
-    let location = use_location();
-    let images = create_resource(
-        move || location.pathname,
-        move |pathname| get_images(pathname.get()),
-    );
+    let images = use_image_page();
 
     view! {
         <a class="text-right no-underline">
             <h1 class="text-4xl font-semibold">"PhotoVelocity"</h1>
         </a>
         <BreadcrumbNav hide_url=false />
+        <SearchBox />
         <FolderView />
         <div class="flex flex-col flex-wrap">
             <Transition>
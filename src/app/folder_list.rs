@@ -1,4 +1,8 @@
-use crate::{app::folder_thumb::FolderThumb, error_template::ErrorTemplate, Folder};
+use crate::{
+    app::folder_thumb::FolderThumb,
+    error_template::{AppError, ErrorTemplate},
+    Folder,
+};
 use leptos::*;
 use leptos_router::*;
 
@@ -21,7 +25,7 @@ pub fn FolderView() -> impl IntoView {
 
 #[component]
 pub fn FolderList(
-    folders: Resource<Memo<String>, Result<Vec<Folder>, ServerFnError>>,
+    folders: Resource<Memo<String>, Result<Vec<Folder>, ServerFnError<AppError>>>,
 ) -> impl IntoView {
     view! {
         <Transition fallback=move || view! { <p>"Loading..."</p> }>
@@ -34,20 +38,26 @@ pub fn FolderList(
                         .get()
                         .map(move |folders| match folders {
                             Err(e) => {
-                                view! { <pre class="error">"Server error: " {e.to_string()}</pre> }
-                                    .into_view()
+                                match AppError::from(e) {
+                                    AppError::NotFound | AppError::LoginRequired => {
+                                        Ok(view! {}.into_view())
+                                    }
+                                    e => Err(e),
+                                }
                             }
                             Ok(folders) => {
-                                folders
-                                    .into_iter()
-                                    .map(move |folder| {
-                                        let (folder_path, _) = create_signal(folder.path);
-                                        view! { <FolderThumb folder_path=folder_path /> }
-                                    })
-                                    .collect_view()
+                                Ok(
+                                    folders
+                                        .into_iter()
+                                        .map(move |folder| {
+                                            let (folder_path, _) = create_signal(folder.path);
+                                            view! { <FolderThumb folder_path=folder_path /> }
+                                        })
+                                        .collect_view(),
+                                )
                             }
                         })
-                        .unwrap_or_default()
+                        .unwrap_or_else(|| Ok(view! {}.into_view()))
                 }}
 
             </ErrorBoundary>
@@ -56,17 +66,18 @@ pub fn FolderList(
 }
 
 #[server]
-pub async fn get_folders(pathname: String) -> Result<Vec<Folder>, ServerFnError> {
+pub async fn get_folders(pathname: String) -> Result<Vec<Folder>, ServerFnError<AppError>> {
     use crate::api::SessionContext;
+    use crate::error_template::server_error;
     use crate::folder::FolderSvc;
     use leptos_axum::extract;
     use log::*;
 
-    let SessionContext(context): SessionContext = extract().await?;
+    let SessionContext(context): SessionContext = extract().await.map_err(server_error)?;
 
     info!("Pathname is: {pathname}");
 
     FolderSvc::list(&context, &pathname)
         .await
-        .map_err(|e| ServerFnError::ServerError(e.to_string()))
+        .map_err(server_error)
 }
@@ -1,16 +1,105 @@
-use crate::{app::image_thumb::*, error_template::ErrorTemplate, Image};
+use crate::{
+    app::image_thumb::*,
+    app::lightbox::{photo_href, photo_name, Lightbox, Neighbour, PHOTO_PARAM},
+    app::slideshow::slideshow_href,
+    error_template::{AppError, ErrorTemplate},
+    layout::{justify, Tile},
+    Image, ImagePage,
+};
+use icondata as i;
 use leptos::*;
+use leptos_icons::*;
 use leptos_router::*;
+use leptos_use::{
+    use_element_size, use_intersection_observer_with_options, UseElementSizeReturn,
+    UseIntersectionObserverOptions,
+};
 use paginate::Pages;
+use std::time::Duration;
+use urlencoding::encode;
+
+/// Images shown per page of a folder
+pub const PAGE_SIZE: usize = 25;
+
+/// Query parameter holding the 1-based page of a folder being shown
+pub const PAGE_PARAM: &str = "page";
+
+/// Query parameter switching a folder from numbered pages to infinite scroll
+pub const SCROLL_PARAM: &str = "scroll";
+
+/// Images fetched each time the end of a scrolling folder comes near
+const SCROLL_SLICE: usize = 50;
+/// How far below the viewport the end of the gallery starts the next fetch
+const SCROLL_MARGIN: &str = "800px";
+
+fn current_page(query: &ParamsMap) -> usize {
+    query
+        .get(PAGE_PARAM)
+        .and_then(|page| page.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// The current folder at `page`, keeping its sort order but closing any photo
+fn page_href(pathname: &str, query: &ParamsMap, page: usize) -> String {
+    photo_href(pathname, &with_page(query, page), None)
+}
+
+/// `page` of the current folder with `photo` open in the lightbox
+fn page_photo_href(pathname: &str, query: &ParamsMap, page: usize, photo: &str) -> String {
+    photo_href(pathname, &with_page(query, page), Some(photo))
+}
+
+fn with_page(query: &ParamsMap, page: usize) -> ParamsMap {
+    let mut query = query.clone();
+    if page > 1 {
+        query.insert(PAGE_PARAM.to_string(), page.to_string());
+    } else {
+        query.remove(PAGE_PARAM);
+    }
+    query
+}
+
+/// The current folder switched between numbered pages and infinite scroll,
+/// starting again from the top
+fn mode_href(pathname: &str, query: &ParamsMap) -> String {
+    let mut query = query.clone();
+    query.remove(PHOTO_PARAM);
+    query.remove(PAGE_PARAM);
+    if query.remove(SCROLL_PARAM).is_none() {
+        query.insert(SCROLL_PARAM.to_string(), "1".to_string());
+    }
+    format!("{pathname}{}", query.to_query_string())
+}
+
+/// The page of the current folder named by the URL
+pub fn use_image_page() -> ImageResource {
+    let location = use_location();
+    let query = use_query_map();
+    create_resource(
+        move || {
+            query.with(|q| {
+                (
+                    location.pathname.get(),
+                    q.get("sort").cloned(),
+                    current_page(q),
+                )
+            })
+        },
+        move |(pathname, sort, page)| {
+            get_images(
+                pathname,
+                sort,
+                Some((page - 1) * PAGE_SIZE),
+                Some(PAGE_SIZE),
+            )
+        },
+    )
+}
 
 #[component]
 fn ImageView() -> impl IntoView {
-    // Creates a reactive value to update the button
-    let location = Signal::derive(|| use_location());
-    let images = create_resource(
-        move || location().pathname,
-        move |pathname| get_images(pathname.get()),
-    );
+    let images = use_image_page();
 
     view! {
         <div class="flex flex-col flex-wrap">
@@ -45,47 +134,368 @@ fn TextView() -> impl IntoView {
     }
 }
 
+/// Height rows are justified towards, in CSS pixels
+const TARGET_ROW_HEIGHT: f64 = 240.0;
+/// Space between thumbnails; matches `gap-2`
+const GAP: f64 = 8.0;
+/// Laid out for until the gallery has been measured, as on the server
+const FALLBACK_WIDTH: f64 = 1200.0;
+/// Measured widths are rounded to this many CSS pixels before rows are
+/// broken again
+const WIDTH_STEP: f64 = 50.0;
+/// How long the gallery must keep its width before rows are broken again
+const RELAYOUT_DELAY: Duration = Duration::from_millis(150);
+/// Requested while the allowed thumbnail sizes are loading
+const FALLBACK_SIZE: u32 = 300;
+
+/// A page of a folder's images, keyed on its path, sort order and page number
+pub type ImageResource =
+    Resource<(String, Option<String>, usize), Result<ImagePage, ServerFnError<AppError>>>;
+
+/// Justified rows of thumbnails. Rows stretch with the gallery as it
+/// resizes, and only where they break is worked out again, so each thumbnail
+/// stays in place rather than being rendered afresh.
 #[component]
-pub fn ImageList(
-    images: Resource<Memo<String>, Result<Vec<Image>, ServerFnError>>,
+fn Gallery(
+    /// May grow, as when scrolling loads more; only the new thumbnails render
+    images: Signal<Vec<Image>>,
+    width: Signal<f64>,
+    sizes: Signal<Vec<u32>>,
+    downloadable: Signal<bool>,
 ) -> impl IntoView {
+    let location = use_location();
+    let query = use_query_map();
+
+    // each tile, and whether its row ends after it
+    let tiles = create_memo(move |_| {
+        let aspect_ratios: Vec<Option<f64>> =
+            images.with(|images| images.iter().map(|image| image.aspect_ratio).collect());
+        justify(
+            &aspect_ratios,
+            width.get(),
+            TARGET_ROW_HEIGHT,
+            GAP,
+            &sizes.get(),
+            FALLBACK_SIZE,
+        )
+        .into_iter()
+        .flat_map(|row| {
+            let last = row.len() - 1;
+            row.into_iter()
+                .enumerate()
+                .map(move |(i, tile)| (tile, i == last))
+        })
+        .collect::<Vec<(Tile, bool)>>()
+    });
+
+    view! {
+        <div class="flex flex-wrap gap-x-2">
+            <For
+                each=move || images.get().into_iter().enumerate()
+                key=|(index, image)| (*index, image.path.clone())
+                children=move |(index, image)| {
+                    let tile = Signal::derive(move || tiles.with(|tiles| tiles.get(index).map(|(tile, _)| *tile)));
+                    let row_end = move || tiles.with(|tiles| tiles.get(index).is_some_and(|(_, end)| *end));
+                    // untracked, so opening the lightbox doesn't re-render the gallery
+                    let href = query.with_untracked(|query| {
+                        photo_href(&location.pathname.get_untracked(), query, Some(photo_name(&image.path)))
+                    });
+                    view! {
+                        <ImageThumb
+                            image_path=image.path.clone()
+                            downloadable=downloadable
+                            placeholder=image.placeholder.clone()
+                            aspect_ratio=image.aspect_ratio
+                            caption=image.caption.clone()
+                            tile=tile
+                            href=Some(href)
+                        />
+                        <Show when=row_end>
+                            <div class="basis-full"></div>
+                        </Show>
+                    }
+                }
+            />
+        </div>
+    }
+}
+
+#[component]
+pub fn ImageList(images: ImageResource) -> impl IntoView {
+    let location = Signal::derive(|| use_location());
+    let downloads = create_resource(
+        move || location().pathname,
+        move |pathname| get_downloads_allowed(pathname.get()),
+    );
+    let downloadable =
+        Signal::derive(move || downloads.get().and_then(|d| d.ok()).unwrap_or(false));
+    let thumb_sizes = create_resource(|| (), |_| get_thumb_sizes());
+    let sizes = Signal::derive(move || thumb_sizes.get().and_then(|s| s.ok()).unwrap_or_default());
+    let query = use_query_map();
+    let photo = move || query.with(|q| q.get(PHOTO_PARAM).cloned());
+    let scrolling = move || query.with(|q| q.get(SCROLL_PARAM).is_some());
+    let gallery = create_node_ref::<html::Div>();
+    let UseElementSizeReturn { width, .. } = use_element_size(gallery);
+    // rows stretch with the gallery between layouts, so only lay them out
+    // again once resizing settles, and only for a change of a whole step
+    let laid_out_width = create_rw_signal(FALLBACK_WIDTH);
+    create_effect(move |_| {
+        let width = (width.get() / WIDTH_STEP).round() * WIDTH_STEP;
+        if width <= 0.0 || width == laid_out_width.get_untracked() {
+            return;
+        }
+        let handle = set_timeout_with_handle(move || laid_out_width.set(width), RELAYOUT_DELAY);
+        on_cleanup(move || {
+            if let Ok(handle) = handle {
+                handle.clear();
+            }
+        });
+    });
+    let gallery_width = Signal::from(laid_out_width);
+
+    // slices loaded by scrolling past the first page, started afresh with each folder
+    let more = create_rw_signal(Vec::<ImagePage>::new());
+    let next = create_rw_signal(None::<String>);
+    let loading = create_rw_signal(false);
+    create_effect(move |_| {
+        let cursor = images.with(|page| {
+            page.as_ref()
+                .and_then(|page| page.as_ref().ok())
+                .and_then(|page| page.next.clone())
+        });
+        more.set(Vec::new());
+        next.set(cursor);
+    });
+
+    let load_more = move || {
+        let Some(cursor) = next.get_untracked() else {
+            return;
+        };
+        let listing = move || {
+            (
+                location.get_untracked().pathname.get_untracked(),
+                query.with_untracked(|q| q.get("sort").cloned()),
+            )
+        };
+        let (pathname, sort) = listing();
+        loading.set(true);
+        spawn_local(async move {
+            let result =
+                get_images_after(pathname.clone(), sort.clone(), cursor, SCROLL_SLICE).await;
+            // the folder or its order may have changed while this was loading
+            if listing() == (pathname, sort) {
+                match result {
+                    Ok(page) => {
+                        next.set(page.next.clone());
+                        more.update(|more| more.push(page));
+                    }
+                    Err(e) => {
+                        logging::error!("Could not load more images: {e}");
+                        next.set(None);
+                    }
+                }
+            }
+            loading.set(false);
+        });
+    };
+
+    let sentinel = create_node_ref::<html::Div>();
+    let (near_end, set_near_end) = create_signal(false);
+    use_intersection_observer_with_options(
+        sentinel,
+        move |entries, _| {
+            if let Some(entry) = entries.last() {
+                set_near_end(entry.is_intersecting());
+            }
+        },
+        UseIntersectionObserverOptions::default().root_margin(SCROLL_MARGIN),
+    );
+    // runs again once each slice lands, so a tall screen keeps filling up
+    create_effect(move |_| {
+        if near_end.get() && scrolling() && next.with(Option::is_some) && !loading.get() {
+            load_more();
+        }
+    });
+
+    let archive_path = move || {
+        let pathname = location().pathname.get();
+        format!("/api/v1/download/{}.zip", encode(&pathname))
+    };
+    let slideshow_path = move || query.with(|q| slideshow_href(&location().pathname.get(), q));
+    let mode_path = move || query.with(|q| mode_href(&location().pathname.get(), q));
+    let action = "inline-flex gap-2 items-center py-1 px-3 text-white bg-gray-500 rounded-full border border-gray-200 hover:bg-gray-900 hover:border-gray-400";
+
     view! {
         <div class="flex flex-wrap">
             <TextView />
-            <Transition fallback=move || view! { <p>"Loading..."</p> }>
-                <ErrorBoundary fallback=|errors| {
-                    view! { <ErrorTemplate errors=errors /> }
-                }>
-
+            <div class="flex gap-2 items-center self-center my-4 ml-auto">
+                <a href=mode_path class=action>
                     {move || {
-                        let images = images.get();
-                        if images.is_none() {
-                            return view! {}.into_view();
+                        if scrolling() {
+                            view! { <Icon icon=i::FaTableCellsSolid /> "Pages" }.into_view()
+                        } else {
+                            view! { <Icon icon=i::FaScrollSolid /> "Scroll" }.into_view()
                         }
-                        let images = images.unwrap();
-                        if images.is_err() {
-                            return view! {}.into_view();
-                        }
-                        let images = images.unwrap();
-                        let pages = Pages::new(images.len(), 25);
-                        let page = pages.with_offset(0);
-                        logging::log!("Pagination {:?}", pages);
-                        let images = &images[page.start..page.end];
-                        let images = images
-                            .iter()
-                            .map(move |image| {
-                                view! { <ImageThumb image_path=image.path.clone() /> }.into_view()
-                            })
-                            .collect_view();
-                        view! { <div class="flex flex-wrap">{images}</div> }.into_view()
                     }}
+                </a>
+                <a href=slideshow_path class=action>
+                    <Icon icon=i::FaPlaySolid />
+                    "Slideshow"
+                </a>
+                <Show when=downloadable>
+                    <a href=archive_path download class=action>
+                        <Icon icon=i::FaFileZipperSolid />
+                        "Download all"
+                    </a>
+                </Show>
+            </div>
+            <div class="w-full" node_ref=gallery>
+                <Transition fallback=move || view! { <p>"Loading..."</p> }>
+                    <ErrorBoundary fallback=|errors| {
+                        view! { <ErrorTemplate errors=errors /> }
+                    }>
 
-                </ErrorBoundary>
-            </Transition>
+                        {move || {
+                            let images = images.get();
+                            if images.is_none() {
+                                return Ok::<_, AppError>(view! {}.into_view());
+                            }
+                            let page = images.unwrap().map_err(AppError::from)?;
+                            let pages = Pages::new(page.total, PAGE_SIZE);
+                            if scrolling() {
+                                // one gallery, so only the true last row is left short
+                                let first = page.images;
+                                let loaded = Signal::derive(move || {
+                                    let mut loaded = first.clone();
+                                    more.with(|more| loaded.extend(more.iter().flat_map(|slice| slice.images.iter().cloned())));
+                                    loaded
+                                });
+                                return Ok(
+                                    view! {
+                                        <Gallery images=loaded width=gallery_width sizes=sizes downloadable=downloadable />
+                                        <Show when=move || loading.get()>
+                                            <p class="my-4">"Loading..."</p>
+                                        </Show>
+                                    }
+                                        .into_view(),
+                                );
+                            }
+                            let pager = view! {
+                                <Pager
+                                    pathname=location.get_untracked().pathname.get_untracked()
+                                    query=query.get_untracked()
+                                    current=page.offset / PAGE_SIZE + 1
+                                    count=pages.page_count()
+                                />
+                            };
+                            if page.images.is_empty() && page.total > 0 {
+                                return Ok(view! { <p class="my-4">"No photos on this page"</p> {pager} }.into_view());
+                            }
+                            let images = page.images;
+                            let images = Signal::derive(move || images.clone());
+                            Ok(
+                                view! {
+                                    <Gallery images=images width=gallery_width sizes=sizes downloadable=downloadable />
+                                    {pager}
+                                }
+                                    .into_view(),
+                            )
+                        }}
+                        {move || {
+                            let photo = photo()?;
+                            let page = images.get()?.ok()?;
+                            let mut images = page.images;
+                            let (mut before, mut after) = (None, None);
+                            if scrolling() {
+                                images.extend(more.get().into_iter().flat_map(|slice| slice.images));
+                            } else {
+                                // the photos either side of the page open on the pages they are on
+                                let pathname = location.get_untracked().pathname.get_untracked();
+                                let query = query.get_untracked();
+                                let current = page.offset / PAGE_SIZE + 1;
+                                let count = Pages::new(page.total, PAGE_SIZE).page_count();
+                                let neighbour = |image: Image, page: usize| Neighbour {
+                                    href: page_photo_href(&pathname, &query, page, photo_name(&image.path)),
+                                    image,
+                                };
+                                before = page.before.map(|image| neighbour(image, if current > 1 { current - 1 } else { count }));
+                                after = page.after.map(|image| neighbour(image, if current < count { current + 1 } else { 1 }));
+                            }
+                            let index = images
+                                .iter()
+                                .position(|image| photo_name(&image.path) == photo)?;
+                            Some(view! { <Lightbox images=images index=index sizes=sizes.get() before=before after=after /> })
+                        }}
+
+                    </ErrorBoundary>
+                </Transition>
+                <div node_ref=sentinel class="h-px"></div>
+            </div>
         </div>
     }
 }
 
+/// Page numbers to offer around `current`, with `None` where a run is skipped
+fn page_numbers(current: usize, count: usize) -> Vec<Option<usize>> {
+    let mut numbers = Vec::new();
+    for page in 1..=count {
+        let near = page.abs_diff(current) <= 2;
+        if page == 1 || page == count || near {
+            numbers.push(Some(page));
+        } else if numbers.last() != Some(&None) {
+            numbers.push(None);
+        }
+    }
+    numbers
+}
+
+/// Links between the pages of a folder; nothing when it fits on one
+#[component]
+fn Pager(pathname: String, query: ParamsMap, current: usize, count: usize) -> impl IntoView {
+    if count <= 1 {
+        return view! {}.into_view();
+    }
+
+    let link = "py-1 px-3 rounded-full hover:bg-gray-900";
+    let numbers = page_numbers(current, count)
+        .into_iter()
+        .map(|page| match page {
+            Some(page) if page == current => {
+                view! { <span class=format!("{link} bg-gray-500") aria-current="page">{page}</span> }
+                    .into_view()
+            }
+            Some(page) => {
+                view! { <A href=page_href(&pathname, &query, page) class=link>{page}</A> }
+                    .into_view()
+            }
+            None => view! { <span class="px-1">"…"</span> }.into_view(),
+        })
+        .collect_view();
+    let prev = (current > 1).then(|| {
+        view! {
+            <A href=page_href(&pathname, &query, current - 1) class=link>
+                <Icon icon=i::FaChevronLeftSolid />
+            </A>
+        }
+    });
+    let next = (current < count).then(|| {
+        view! {
+            <A href=page_href(&pathname, &query, current + 1) class=link>
+                <Icon icon=i::FaChevronRightSolid />
+            </A>
+        }
+    });
+
+    view! {
+        <nav class="flex gap-1 justify-center items-center my-4" aria-label="Pages">
+            {prev}
+            {numbers}
+            {next}
+        </nav>
+    }
+    .into_view()
+}
+
 #[server]
 pub async fn get_text(pathname: String) -> Result<String, ServerFnError> {
     use crate::api::SessionContext;
@@ -102,18 +512,77 @@ pub async fn get_text(pathname: String) -> Result<String, ServerFnError> {
         .unwrap_or_default())
 }
 
+/// `limit` images of a folder from `offset`, or all of them without a limit
 #[server]
-pub async fn get_images(pathname: String) -> Result<Vec<Image>, ServerFnError> {
+pub async fn get_images(
+    pathname: String,
+    sort: Option<String>,
+    offset: Option<usize>,
+    limit: Option<usize>,
+) -> Result<ImagePage, ServerFnError<AppError>> {
     use crate::api::SessionContext;
+    use crate::error_template::server_error;
     use crate::image::ImageSvc;
     use leptos_axum::extract;
     use log::*;
 
-    let SessionContext(context): SessionContext = extract().await?;
+    let SessionContext(context): SessionContext = extract().await.map_err(server_error)?;
 
     info!("Pathname is: {pathname}");
+    let sort = parse_sort(sort)?;
 
-    ImageSvc::list(&context, &pathname)
+    ImageSvc::list_page(&context, &pathname, sort, offset.unwrap_or(0), limit)
         .await
-        .map_err(|e| ServerFnError::ServerError(e.to_string()))
+        .map_err(server_error)
+}
+
+/// Up to `limit` images of a folder following `cursor`, as handed out in
+/// [`ImagePage::next`]
+#[server]
+pub async fn get_images_after(
+    pathname: String,
+    sort: Option<String>,
+    cursor: String,
+    limit: usize,
+) -> Result<ImagePage, ServerFnError<AppError>> {
+    use crate::api::SessionContext;
+    use crate::error_template::server_error;
+    use crate::image::ImageSvc;
+    use leptos_axum::extract;
+
+    let SessionContext(context): SessionContext = extract().await.map_err(server_error)?;
+    let sort = parse_sort(sort)?;
+
+    ImageSvc::list_after(&context, &pathname, sort, &cursor, limit)
+        .await
+        .map_err(server_error)
+}
+
+#[cfg(feature = "ssr")]
+pub(crate) fn parse_sort(
+    sort: Option<String>,
+) -> Result<Option<crate::sort::SortOrder>, ServerFnError<AppError>> {
+    use crate::error_template::server_error;
+    use std::str::FromStr;
+
+    sort.map(|sort| crate::sort::SortOrder::from_str(&sort))
+        .transpose()
+        .map_err(|e| server_error(AppError::BadRequest(e.to_string())))
+}
+
+#[server]
+pub async fn get_thumb_sizes() -> Result<Vec<u32>, ServerFnError> {
+    Ok(crate::thumbnail::thumb_sizes())
+}
+
+#[server]
+pub async fn get_downloads_allowed(pathname: String) -> Result<bool, ServerFnError> {
+    use crate::settings::FolderSettings;
+
+    if pathname.contains("..") {
+        return Err(ServerFnError::ServerError(
+            "path may not contain '..'".to_string(),
+        ));
+    }
+    Ok(FolderSettings::load(&pathname).await.downloads)
 }
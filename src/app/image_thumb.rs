@@ -1,22 +1,163 @@
+use crate::layout::Tile;
+use crate::placeholder::data_url;
+use icondata as i;
 use leptos::*;
+use leptos_icons::*;
 use urlencoding::encode;
 
+/// Thumbnails are rendered to fit inside a square of this many CSS pixels
+const THUMB_BOX: f64 = 300.0;
+
+/// Width and height of a thumbnail with `aspect_ratio` scaled into `THUMB_BOX`
+fn thumb_size(aspect_ratio: f64) -> (u32, u32) {
+    if aspect_ratio >= 1.0 {
+        (THUMB_BOX as u32, (THUMB_BOX / aspect_ratio).round() as u32)
+    } else {
+        ((THUMB_BOX * aspect_ratio).round() as u32, THUMB_BOX as u32)
+    }
+}
+
 #[component]
-pub fn ImageThumb(image_path: String) -> impl IntoView {
-    let encoded = encode(&image_path);
-    let img_path_x1 = format!("/api/v1/imageThumb/300/{encoded}",);
-    let img_path_x2 = format!("/api/v1/imageThumb/600/{encoded}",);
+pub fn ImageThumb(
+    image_path: String,
+    #[prop(into, default = false.into())] downloadable: MaybeSignal<bool>,
+    /// BlurHash shown until the thumbnail has loaded
+    #[prop(default = None)]
+    placeholder: Option<String>,
+    /// Lets the thumbnail hold its space before it loads, so the page doesn't jump
+    #[prop(default = None)]
+    aspect_ratio: Option<f64>,
+    /// Box and thumbnail sizes from a justified layout, which change as the
+    /// gallery is laid out again
+    #[prop(into, default = None.into())]
+    tile: MaybeSignal<Option<Tile>>,
+    /// Where clicking the thumbnail leads, usually the lightbox
+    #[prop(default = None)]
+    href: Option<String>,
+    /// Shown under the thumbnail
+    #[prop(default = None)]
+    caption: Option<String>,
+) -> impl IntoView {
+    let size = move || match tile.get() {
+        Some(tile) => Some((tile.width.round() as u32, tile.height.round() as u32)),
+        None => aspect_ratio
+            .filter(|ratio| ratio.is_finite() && *ratio > 0.0)
+            .map(thumb_size),
+    };
+    let (loaded, set_loaded) = create_signal(placeholder.is_none());
+    // decoded in the browser only, so the page carries the short hash rather
+    // than a bitmap for every thumbnail
+    let (placeholder_url, set_placeholder_url) = create_signal(None::<String>);
+    create_effect(move |_| {
+        if !loaded.get_untracked() {
+            set_placeholder_url(placeholder.as_deref().and_then(data_url));
+        }
+    });
+    let img_ref = create_node_ref::<html::Img>();
+    // a thumbnail from the browser cache can finish before hydration attaches `on:load`
+    img_ref.on_load(move |img| {
+        if img.complete() {
+            set_loaded(true);
+        }
+    });
+    let tiled = move || tile.with(Option::is_some);
+    let box_class = move || {
+        if tiled() {
+            return "flex overflow-hidden relative w-full min-w-0 rounded-2xl group bg-center bg-cover"
+                .to_string();
+        }
+        let loading = if loaded() || size().is_some() {
+            ""
+        } else {
+            " min-w-[300px] min-h-[200px]"
+        };
+        format!("flex overflow-hidden relative justify-center items-center self-center m-2 rounded-2xl group max-h-[310px] bg-center bg-cover{loading}")
+    };
+    let box_style = move || {
+        let mut style = tile
+            .get()
+            .map(|tile| format!("aspect-ratio: {} / {};", tile.width, tile.height))
+            .unwrap_or_default();
+        if !loaded() {
+            placeholder_url.with(|url| {
+                if let Some(url) = url {
+                    style.push_str(&format!(" background-image: url({url})"));
+                }
+            });
+        }
+        style
+    };
+    let img_class = move || {
+        if tiled() {
+            "object-cover w-full h-full cursor-pointer"
+        } else {
+            "cursor-pointer"
+        }
+    };
+    // only ever move up a size, so laying the gallery out again never
+    // fetches a thumbnail the browser could scale down from
+    let thumb_sizes = create_memo(move |previous: Option<&(u32, u32)>| {
+        let (x1, x2) = tile
+            .get()
+            .map_or((300, 600), |tile| (tile.size, tile.size_2x));
+        previous.map_or((x1, x2), |(p1, p2)| ((*p1).max(x1), (*p2).max(x2)))
+    });
+    let encoded = encode(&image_path).into_owned();
+    let thumb_path = move |size: u32| format!("/api/v1/imageThumb/{size}/{encoded}");
+    let img_path_x1 = {
+        let thumb_path = thumb_path.clone();
+        move || thumb_path(thumb_sizes.get().0)
+    };
+    let img_srcset = move || {
+        let (x1, x2) = thumb_sizes.get();
+        format!("{} 1x, {} 2x", thumb_path(x1), thumb_path(x2))
+    };
+    let original_path = format!("/api/v1/original/{}", encode(&image_path));
+    let figure_class = move || {
+        if tiled() {
+            "flex flex-col gap-1 mb-2 min-w-0"
+        } else {
+            "flex flex-col gap-1 min-w-0"
+        }
+    };
+    let figure_style = move || tile.get().map(|tile| tile.flex());
+    let caption = caption.map(|caption| {
+        view! {
+            <figcaption class="text-sm text-gray-300 truncate" title=caption.clone()>
+                {caption}
+            </figcaption>
+        }
+    });
 
     view! {
-        <div class="flex overflow-hidden relative justify-center items-center self-center m-2 rounded-2xl max-h-[310px]">
-            <picture>
-                <img
-                    class="cursor-pointer"
-                    loading="lazy"
-                    src=img_path_x1.to_owned()
-                    srcset=format!("{} 1x, {} 2x", img_path_x1, img_path_x2)
-                />
-            </picture>
-        </div>
+        <figure class=figure_class style=figure_style>
+            <div class=box_class style=box_style>
+                <Show when=move || downloadable.get()>
+                    <a
+                        href=original_path.clone()
+                        download
+                        title="Download original"
+                        class="absolute right-2 bottom-2 p-2 text-white rounded-full opacity-0 transition-opacity group-hover:opacity-100 bg-black/60"
+                    >
+                        <Icon icon=i::FaDownloadSolid />
+                    </a>
+                </Show>
+                <a href=href class="contents">
+                    <picture class="contents">
+                        <img
+                            node_ref=img_ref
+                            on:load=move |_| set_loaded(true)
+                            class=img_class
+                            width=move || size().map(|(width, _)| width)
+                            height=move || size().map(|(_, height)| height)
+                            loading="lazy"
+                            src=img_path_x1
+                            srcset=img_srcset
+                        />
+                    </picture>
+                </a>
+            </div>
+            {caption}
+        </figure>
     }
 }
@@ -0,0 +1,223 @@
+use crate::{layout::pick_size, Image};
+use icondata as i;
+use leptos::*;
+use leptos_icons::*;
+use leptos_router::*;
+use urlencoding::encode;
+
+/// Query parameter naming the photo open in the lightbox, so it can be shared
+pub const PHOTO_PARAM: &str = "photo";
+
+/// Rungs of the thumbnail ladder the lightbox shows, smallest first
+const LIGHTBOX_SIZES: [u32; 2] = [1200, 2400];
+
+/// Horizontal travel, in CSS pixels, that counts as a swipe
+const SWIPE_DISTANCE: i32 = 50;
+
+/// File name of an image, as used for `?photo=`
+pub fn photo_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// The current folder with `photo` open, or closed when `None`, keeping any
+/// other query parameters such as `sort`
+pub fn photo_href(pathname: &str, query: &ParamsMap, photo: Option<&str>) -> String {
+    let mut query = query.clone();
+    match photo {
+        Some(photo) => {
+            query.insert(PHOTO_PARAM.to_string(), photo.to_string());
+        }
+        None => {
+            query.remove(PHOTO_PARAM);
+        }
+    }
+    format!("{pathname}{}", query.to_query_string())
+}
+
+/// `srcset` over the lightbox sizes the server allows, so the browser picks
+/// one to suit the screen
+pub fn srcset(image: &Image, sizes: &[u32]) -> (String, String) {
+    let mut ladder: Vec<u32> = LIGHTBOX_SIZES
+        .iter()
+        .filter_map(|size| pick_size(sizes, f64::from(*size)))
+        .collect();
+    ladder.dedup();
+    if ladder.is_empty() {
+        ladder = LIGHTBOX_SIZES.to_vec();
+    }
+
+    // thumbnails fit a square, so portrait ones are narrower than their size
+    let narrowing = image
+        .aspect_ratio
+        .filter(|r| *r > 0.0)
+        .unwrap_or(1.0)
+        .min(1.0);
+    let encoded = encode(&image.path);
+    let srcset = ladder
+        .iter()
+        .map(|size| {
+            let width = (f64::from(*size) * narrowing).round() as u32;
+            format!("/api/v1/imageThumb/{size}/{encoded} {width}w")
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    (
+        format!("/api/v1/imageThumb/{}/{encoded}", ladder[0]),
+        srcset,
+    )
+}
+
+/// A photo beyond the images given to the lightbox, such as the last one
+/// of the previous page, and where it opens
+#[derive(Debug, Clone)]
+pub struct Neighbour {
+    pub image: Image,
+    pub href: String,
+}
+
+/// Full-screen view of one image in a folder, with links to its neighbours.
+/// Arrow keys and swipes step through the folder, Escape closes.
+#[component]
+pub fn Lightbox(
+    images: Vec<Image>,
+    index: usize,
+    sizes: Vec<u32>,
+    /// Stepped back to from the first of `images`, which otherwise wraps to the last
+    #[prop(default = None)]
+    before: Option<Neighbour>,
+    /// Stepped on to from the last of `images`, which otherwise wraps to the first
+    #[prop(default = None)]
+    after: Option<Neighbour>,
+) -> impl IntoView {
+    let location = use_location();
+    let query = use_query_map();
+    let href = move |image: &Image| {
+        photo_href(
+            &location.pathname.get_untracked(),
+            &query.get_untracked(),
+            Some(photo_name(&image.path)),
+        )
+    };
+
+    let count = images.len();
+    let image = images[index].clone();
+    let (prev, prev_href) = match before.filter(|_| index == 0) {
+        Some(Neighbour { image, href }) => (image, href),
+        None => {
+            let prev = images[(index + count - 1) % count].clone();
+            let prev_href = href(&prev);
+            (prev, prev_href)
+        }
+    };
+    let (next, next_href) = match after.filter(|_| index + 1 == count) {
+        Some(Neighbour { image, href }) => (image, href),
+        None => {
+            let next = images[(index + 1) % count].clone();
+            let next_href = href(&next);
+            (next, next_href)
+        }
+    };
+    let close_href = photo_href(
+        &location.pathname.get_untracked(),
+        &query.get_untracked(),
+        None,
+    );
+
+    // stepping through photos replaces the history entry, so Back leaves the lightbox
+    let navigate = use_navigate();
+    let go = move |href: &str| {
+        navigate(
+            href,
+            NavigateOptions {
+                replace: true,
+                scroll: false,
+                ..Default::default()
+            },
+        )
+    };
+
+    let handle = {
+        let (go, prev_href, next_href, close_href) = (
+            go.clone(),
+            prev_href.clone(),
+            next_href.clone(),
+            close_href.clone(),
+        );
+        window_event_listener(ev::keydown, move |e| match e.key().as_str() {
+            "ArrowLeft" => go(&prev_href),
+            "ArrowRight" => go(&next_href),
+            "Escape" => go(&close_href),
+            _ => {}
+        })
+    };
+    on_cleanup(move || handle.remove());
+
+    let (swipe_start, set_swipe_start) = create_signal(None::<i32>);
+    let on_swipe_end = {
+        let (prev_href, next_href) = (prev_href.clone(), next_href.clone());
+        move |e: ev::PointerEvent| {
+            let Some(start) = swipe_start.get_untracked() else {
+                return;
+            };
+            set_swipe_start(None);
+            let travel = e.client_x() - start;
+            if travel > SWIPE_DISTANCE {
+                go(&prev_href);
+            } else if travel < -SWIPE_DISTANCE {
+                go(&next_href);
+            }
+        }
+    };
+
+    let (src, srcset_value) = srcset(&image, &sizes);
+    let preload = [prev, next]
+        .iter()
+        .filter(|neighbour| neighbour.path != image.path)
+        .map(|neighbour| {
+            let (src, srcset_value) = srcset(neighbour, &sizes);
+            view! { <img src=src srcset=srcset_value sizes="100vw" alt="" /> }
+        })
+        .collect_view();
+    let button = "absolute p-3 text-white rounded-full bg-black/60 hover:bg-black/90";
+
+    view! {
+        <div
+            class="flex fixed inset-0 z-50 justify-center items-center select-none bg-black/90 touch-pan-y"
+            role="dialog"
+            aria-modal="true"
+            on:pointerdown=move |e| set_swipe_start(Some(e.client_x()))
+            on:pointerup=on_swipe_end
+        >
+            <img
+                class="object-contain max-w-full max-h-full"
+                src=src
+                srcset=srcset_value
+                sizes="100vw"
+                alt=photo_name(&image.path).to_string()
+                draggable="false"
+            />
+            {image
+                .caption
+                .clone()
+                .map(|caption| {
+                    view! {
+                        <p class="absolute bottom-4 left-1/2 py-2 px-4 max-w-[80%] text-center rounded -translate-x-1/2 bg-black/60">
+                            {caption}
+                        </p>
+                    }
+                })}
+            <A href=prev_href replace=true class=format!("{button} left-4 top-1/2 -translate-y-1/2")>
+                <Icon icon=i::FaChevronLeftSolid />
+            </A>
+            <A href=next_href replace=true class=format!("{button} right-4 top-1/2 -translate-y-1/2")>
+                <Icon icon=i::FaChevronRightSolid />
+            </A>
+            <A href=close_href replace=true class=format!("{button} top-4 right-4")>
+                <Icon icon=i::FaXmarkSolid />
+            </A>
+            <div class="hidden" aria-hidden="true">
+                {preload}
+            </div>
+        </div>
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{
+    app::image_list::{get_thumb_sizes, PAGE_PARAM, PAGE_SIZE},
+    app::lightbox::{photo_name, PHOTO_PARAM},
+    error_template::{AppError, ErrorTemplate},
+    layout::pick_size,
+    SearchHit,
+};
+use icondata as i;
+use leptos::*;
+use leptos_icons::*;
+use leptos_router::*;
+use urlencoding::encode;
+
+/// Query parameter holding a library search, so results can be linked to
+pub const SEARCH_PARAM: &str = "q";
+
+/// Side of a result thumbnail, in CSS pixels; matches `w-24 h-24`
+const RESULT_THUMB: f64 = 96.0;
+/// Requested while the allowed thumbnail sizes are loading
+const FALLBACK_SIZE: u32 = 300;
+
+/// Where a hit is seen: a folder, or an image open in its folder's lightbox
+/// on the page it falls on
+fn hit_href(hit: &SearchHit) -> String {
+    if hit.is_folder {
+        return hit.folder.clone();
+    }
+    let mut query = ParamsMap::new();
+    let page = hit.position.unwrap_or(0) / PAGE_SIZE + 1;
+    if page > 1 {
+        query.insert(PAGE_PARAM.to_string(), page.to_string());
+    }
+    query.insert(PHOTO_PARAM.to_string(), photo_name(&hit.path).to_string());
+    format!("{}{}", hit.folder, query.to_query_string())
+}
+
+/// Search box for the whole library, listing what matches below it
+#[component]
+pub fn SearchBox() -> impl IntoView {
+    let location = use_location();
+    let query = use_query_map();
+    let term = move || query.with(|q| q.get(SEARCH_PARAM).cloned().unwrap_or_default());
+    let hits = create_resource(term, |term| async move {
+        if term.trim().is_empty() {
+            Ok(Vec::new())
+        } else {
+            search(term).await
+        }
+    });
+    let thumb_sizes = create_resource(|| (), |_| get_thumb_sizes());
+
+    view! {
+        <Form method="GET" action=move || location.pathname.get() class="my-4">
+            <label class="flex gap-2 items-center py-1 px-3 rounded-full border border-gray-500 focus-within:border-gray-200">
+                <Icon icon=i::FaMagnifyingGlassSolid />
+                <input
+                    type="search"
+                    name=SEARCH_PARAM
+                    value=term
+                    placeholder="Search folders, file names and captions"
+                    class="w-full bg-transparent outline-none"
+                />
+            </label>
+        </Form>
+        <Transition fallback=move || view! { <p>"Searching..."</p> }>
+            <ErrorBoundary fallback=|errors| {
+                view! { <ErrorTemplate errors=errors /> }
+            }>
+                {move || {
+                    let Some(hits) = hits.get() else {
+                        return Ok::<_, AppError>(view! {}.into_view());
+                    };
+                    let hits = hits.map_err(AppError::from)?;
+                    if hits.is_empty() {
+                        let searched = !term().trim().is_empty();
+                        return Ok(searched.then(|| view! { <p class="my-4">"Nothing found"</p> }).into_view());
+                    }
+                    let sizes = thumb_sizes.get().and_then(|s| s.ok()).unwrap_or_default();
+                    let size = pick_size(&sizes, RESULT_THUMB * 2.0).unwrap_or(FALLBACK_SIZE);
+                    let results = hits
+                        .into_iter()
+                        .map(|hit| {
+                            let picture = if hit.is_folder {
+                                view! { <Icon icon=i::FaFolderOpenSolid class="w-12 h-12" /> }.into_view()
+                            } else {
+                                let src = format!("/api/v1/imageThumb/{size}/{}", encode(&hit.path));
+                                view! { <img src=src alt="" loading="lazy" class="object-cover w-24 h-24 rounded" /> }.into_view()
+                            };
+                            view! {
+                                <li>
+                                    <a href=hit_href(&hit) class="flex gap-4 items-center p-2 rounded hover:bg-gray-900">
+                                        <span class="flex flex-none justify-center items-center w-24 h-24">{picture}</span>
+                                        <span class="min-w-0">
+                                            <span class="block truncate">{hit.path.clone()}</span>
+                                            <span class="block text-gray-400 truncate">{hit.text.clone()}</span>
+                                        </span>
+                                    </a>
+                                </li>
+                            }
+                        })
+                        .collect_view();
+                    Ok(view! { <ul class="flex flex-col gap-1 my-4">{results}</ul> }.into_view())
+                }}
+            </ErrorBoundary>
+        </Transition>
+    }
+}
+
+/// Folders and images matching every word of `query` that the caller may see
+#[server]
+pub async fn search(query: String) -> Result<Vec<SearchHit>, ServerFnError<AppError>> {
+    use crate::api::SessionContext;
+    use crate::error_template::server_error;
+    use crate::image::ImageSvc;
+    use crate::search::folder_visible;
+    use leptos_axum::extract;
+    use std::collections::HashMap;
+
+    let SessionContext(context): SessionContext = extract().await.map_err(server_error)?;
+    let hits = context.search_index.search(&query, &context.auth);
+
+    // the index lags the disk, so `.hide` files and folder listings have the
+    // last word; each folder is checked and listed once however many hits it has
+    let mut visible: HashMap<String, bool> = HashMap::new();
+    let mut positions: HashMap<String, HashMap<String, usize>> = HashMap::new();
+    for hit in &hits {
+        if !visible.contains_key(&hit.folder) {
+            let shown = folder_visible(&hit.folder, &context.auth).await;
+            visible.insert(hit.folder.clone(), shown);
+        }
+        if hit.is_folder || !visible[&hit.folder] || positions.contains_key(&hit.folder) {
+            continue;
+        }
+        let listing = ImageSvc::list_sorted(&context, &hit.folder, None)
+            .await
+            .map(|images| {
+                images
+                    .into_iter()
+                    .enumerate()
+                    .map(|(position, image)| (image.path, position))
+                    .collect()
+            })
+            .unwrap_or_default();
+        positions.insert(hit.folder.clone(), listing);
+    }
+
+    Ok(hits
+        .into_iter()
+        .filter(|hit| visible[&hit.folder])
+        .filter_map(|mut hit| {
+            if !hit.is_folder {
+                hit.position = Some(*positions.get(&hit.folder)?.get(&hit.path)?);
+            }
+            Some(hit)
+        })
+        .collect())
+}
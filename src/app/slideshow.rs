@@ -0,0 +1,260 @@
+use crate::{
+    app::image_list::get_thumb_sizes,
+    app::lightbox::srcset,
+    error_template::{AppError, ErrorTemplate},
+    Image,
+};
+use icondata as i;
+use leptos::*;
+use leptos_icons::*;
+use leptos_router::*;
+use std::time::Duration;
+
+/// Seconds each photo is shown for, unless `?interval=` says otherwise
+const DEFAULT_INTERVAL: u64 = 5;
+/// Transitions take a second, so anything shorter never settles
+const MIN_INTERVAL: u64 = 2;
+
+/// Query parameter turning a folder page into its slideshow; a query rather
+/// than a path prefix, which a folder of the same name would shadow
+pub const SLIDESHOW_PARAM: &str = "slideshow";
+
+/// How one photo gives way to the next, from `?transition=`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlideTransition {
+    /// The last photo dissolves into the next
+    #[default]
+    Dissolve,
+    /// The last photo fades to black, then the next fades in
+    Fade,
+    None,
+}
+
+impl SlideTransition {
+    fn parse(transition: Option<&str>) -> Self {
+        match transition {
+            Some("fade") => SlideTransition::Fade,
+            Some("none") => SlideTransition::None,
+            _ => SlideTransition::Dissolve,
+        }
+    }
+
+    fn entering(&self) -> &'static str {
+        match self {
+            SlideTransition::Dissolve => "animate-slide-in",
+            SlideTransition::Fade => "animate-slide-in-late",
+            SlideTransition::None => "",
+        }
+    }
+
+    fn leaving(&self) -> &'static str {
+        match self {
+            SlideTransition::Dissolve => "animate-slide-out-slow",
+            SlideTransition::Fade => "animate-slide-out",
+            SlideTransition::None => "",
+        }
+    }
+}
+
+/// Link to the slideshow of `folder`, keeping its sort order
+pub fn slideshow_href(folder: &str, query: &ParamsMap) -> String {
+    let mut params = ParamsMap::new();
+    params.insert(SLIDESHOW_PARAM.to_string(), String::new());
+    if let Some(sort) = query.get("sort") {
+        params.insert("sort".to_string(), sort.clone());
+    }
+    format!("{folder}{}", params.to_query_string())
+}
+
+fn toggle_fullscreen() {
+    let document = document();
+    if document.fullscreen_element().is_some() {
+        document.exit_fullscreen();
+    } else if let Some(root) = document.document_element() {
+        if let Err(e) = root.request_fullscreen() {
+            logging::warn!("Could not go fullscreen: {:?}", e);
+        }
+    }
+}
+
+/// Full-screen, self-advancing view of a folder, e.g.
+/// `/Holidays?slideshow&interval=8&transition=fade`. Space pauses, the arrow
+/// keys step and `f` toggles fullscreen.
+#[component]
+pub fn Slideshow() -> impl IntoView {
+    let location = use_location();
+    let query = use_query_map();
+    let folder = move || location.pathname.get();
+    let images = create_resource(
+        move || (folder(), query.with(|q| q.get("sort").cloned())),
+        move |(folder, sort)| get_slideshow_images(folder, sort),
+    );
+    let thumb_sizes = create_resource(|| (), |_| get_thumb_sizes());
+    let interval = move || {
+        query
+            .with(|q| q.get("interval").and_then(|i| i.parse().ok()))
+            .unwrap_or(DEFAULT_INTERVAL)
+            .max(MIN_INTERVAL)
+    };
+    let transition =
+        move || query.with(|q| SlideTransition::parse(q.get("transition").map(String::as_str)));
+
+    let (current, set_current) = create_signal(0usize);
+    let (previous, set_previous) = create_signal(None::<usize>);
+    let (paused, set_paused) = create_signal(false);
+    let step = move |by: isize| {
+        let count = untrack(move || {
+            images.with(|i| {
+                i.as_ref()
+                    .and_then(|i| i.as_ref().ok())
+                    .map_or(0, |i| i.len())
+            })
+        });
+        if count == 0 {
+            return;
+        }
+        let from = current.get_untracked() % count;
+        set_previous(Some(from));
+        set_current((from as isize + by).rem_euclid(count as isize) as usize);
+    };
+
+    // effects only run in the browser, so the server renders the first photo
+    create_effect(move |_| {
+        let every = Duration::from_secs(interval());
+        let handle = set_interval_with_handle(
+            move || {
+                if !paused.get_untracked() {
+                    step(1);
+                }
+            },
+            every,
+        );
+        on_cleanup(move || {
+            if let Ok(handle) = handle {
+                handle.clear();
+            }
+        });
+    });
+
+    let keys = window_event_listener(ev::keydown, move |e| match e.key().as_str() {
+        " " => {
+            e.prevent_default();
+            set_paused.update(|p| *p = !*p);
+        }
+        "ArrowLeft" => step(-1),
+        "ArrowRight" => step(1),
+        "f" => toggle_fullscreen(),
+        _ => {}
+    });
+    on_cleanup(move || keys.remove());
+
+    let close_href = move || folder();
+    let button = "p-3 text-white rounded-full hover:bg-black/90";
+
+    view! {
+        <div class="overflow-hidden fixed inset-0 z-50 bg-black">
+            <Transition fallback=move || view! { <p>"Loading..."</p> }>
+                <ErrorBoundary fallback=|errors| {
+                    view! { <ErrorTemplate errors=errors /> }
+                }>
+                    {move || {
+                        let Some(images) = images.get() else {
+                            return Ok::<_, AppError>(view! {}.into_view());
+                        };
+                        let images = images.map_err(AppError::from)?;
+                        if images.is_empty() {
+                            return Ok(view! { <p class="mt-8">"No photos in this folder"</p> }.into_view());
+                        }
+                        let sizes = thumb_sizes.get().and_then(|s| s.ok()).unwrap_or_default();
+                        let transition = transition();
+                        let slide = |index: usize, class: &'static str| {
+                            let (src, srcset_value) = srcset(&images[index], &sizes);
+                            view! {
+                                <img
+                                    class=format!("object-contain absolute inset-0 w-full h-full {class}")
+                                    src=src
+                                    srcset=srcset_value
+                                    sizes="100vw"
+                                    alt=""
+                                />
+                            }
+                        };
+                        let shown = current.get() % images.len();
+                        // `step` sets the previous photo before the current one, so it is
+                        // read untracked; once faded out it is dropped without rendering
+                        // the shown photo again, which would restart its animation
+                        let (left, set_left) = create_signal(false);
+                        let leaving = previous
+                            .get_untracked()
+                            .map(|p| p % images.len())
+                            .filter(|p| *p != shown && transition != SlideTransition::None)
+                            .map(|p| {
+                                slide(p, transition.leaving())
+                                    .on(ev::animationend, move |_| set_left(true))
+                            });
+                        let leaving = move || leaving.clone().filter(|_| !left.get());
+                        let (next, next_srcset) = srcset(&images[(shown + 1) % images.len()], &sizes);
+                        Ok(
+                            view! {
+                                {leaving}
+                                {slide(shown, transition.entering())}
+                                <div class="hidden" aria-hidden="true">
+                                    <img src=next srcset=next_srcset sizes="100vw" alt="" />
+                                </div>
+                            }
+                                .into_view(),
+                        )
+                    }}
+                </ErrorBoundary>
+            </Transition>
+            <div class="flex absolute bottom-4 left-1/2 gap-2 p-2 text-white rounded-full opacity-0 transition-opacity -translate-x-1/2 hover:opacity-100 focus-within:opacity-100 bg-black/60">
+                <button class=button title="Previous" on:click=move |_| step(-1)>
+                    <Icon icon=i::FaChevronLeftSolid />
+                </button>
+                <button
+                    class=button
+                    title=move || if paused() { "Resume" } else { "Pause" }
+                    on:click=move |_| set_paused.update(|p| *p = !*p)
+                >
+                    {move || {
+                        if paused() {
+                            view! { <Icon icon=i::FaPlaySolid /> }
+                        } else {
+                            view! { <Icon icon=i::FaPauseSolid /> }
+                        }
+                    }}
+                </button>
+                <button class=button title="Next" on:click=move |_| step(1)>
+                    <Icon icon=i::FaChevronRightSolid />
+                </button>
+                <button class=button title="Fullscreen" on:click=move |_| toggle_fullscreen()>
+                    <Icon icon=i::FaExpandSolid />
+                </button>
+                <A href=close_href class=button>
+                    <Icon icon=i::FaXmarkSolid />
+                </A>
+            </div>
+        </div>
+    }
+}
+
+/// Every image of a folder in slideshow order. Unlike a gallery page this
+/// leaves out placeholders, which a slideshow never shows.
+#[server]
+pub async fn get_slideshow_images(
+    folder: String,
+    sort: Option<String>,
+) -> Result<Vec<Image>, ServerFnError<AppError>> {
+    use crate::api::SessionContext;
+    use crate::app::image_list::parse_sort;
+    use crate::error_template::server_error;
+    use crate::image::ImageSvc;
+    use leptos_axum::extract;
+
+    let SessionContext(context): SessionContext = extract().await.map_err(server_error)?;
+    let sort = parse_sort(sort)?;
+
+    ImageSvc::list_sorted(&context, &folder, sort)
+        .await
+        .map_err(server_error)
+}
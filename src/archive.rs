@@ -0,0 +1,122 @@
+use async_zip::base::write::ZipFileWriter;
+use async_zip::{Compression, ZipEntryBuilder};
+use axum::body::Bytes;
+use futures::stream::{self, Stream, StreamExt};
+use log::*;
+use std::io;
+use std::path::PathBuf;
+use tokio::io::DuplexStream;
+use tokio::sync::oneshot;
+use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
+use tokio_util::io::ReaderStream;
+
+// Bytes buffered between the archive writer and the response body
+const PIPE_CAPACITY: usize = 256 * 1024;
+
+/// A file to place in an archive under `name`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveEntry {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Stream a ZIP of `entries` as it is written, so only `PIPE_CAPACITY` bytes
+/// are ever held in memory. Photos are already compressed, so entries are stored.
+/// Files that cannot be opened are left out; any other failure ends the
+/// stream with an error, so the client sees an aborted download rather than
+/// a truncated archive.
+pub fn zip_stream(entries: Vec<ArchiveEntry>) -> impl Stream<Item = io::Result<Bytes>> {
+    let (writer, reader) = tokio::io::duplex(PIPE_CAPACITY);
+    let (failed, failure) = oneshot::channel::<io::Error>();
+
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(writer, entries).await {
+            error!("Could not write archive: {:?}", e);
+            let _ = failed.send(io::Error::other(e.to_string()));
+        }
+    });
+
+    // only yields once everything written has been read
+    let failure = stream::once(failure).filter_map(|failure| async move { failure.ok().map(Err) });
+    ReaderStream::new(reader).chain(failure)
+}
+
+async fn write_archive(writer: DuplexStream, entries: Vec<ArchiveEntry>) -> anyhow::Result<()> {
+    let mut zip = ZipFileWriter::with_tokio(writer);
+    for entry in entries {
+        let file = match tokio::fs::File::open(&entry.path).await {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Leaving {:?} out of archive: {:?}", entry.path, e);
+                continue;
+            }
+        };
+        write_entry(&mut zip, &entry, file).await?;
+    }
+    zip.close().await?;
+    Ok(())
+}
+
+async fn write_entry(
+    zip: &mut ZipFileWriter<Compat<DuplexStream>>,
+    entry: &ArchiveEntry,
+    file: tokio::fs::File,
+) -> anyhow::Result<()> {
+    let builder = ZipEntryBuilder::new(entry.name.clone().into(), Compression::Stored);
+    let mut writer = zip.write_entry_stream(builder).await?;
+    futures::io::copy(&mut file.compat(), &mut writer).await?;
+    writer.close().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn it_streams_a_zip_archive() {
+        let entries = vec![ArchiveEntry {
+            name: "Cargo.toml".to_string(),
+            path: PathBuf::from("Cargo.toml"),
+        }];
+        let chunks: Vec<Bytes> = zip_stream(entries)
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+        let data: Vec<u8> = chunks.concat();
+        let cargo_toml = std::fs::read("Cargo.toml").unwrap();
+
+        assert!(data.starts_with(b"PK\x03\x04"));
+        assert!(data
+            .windows(cargo_toml.len())
+            .any(|w| w == cargo_toml.as_slice()));
+        // end of central directory record
+        assert!(data.windows(4).any(|w| w == b"PK\x05\x06"));
+    }
+
+    #[tokio::test]
+    pub async fn it_leaves_out_files_it_cannot_open() {
+        let entries = vec![
+            ArchiveEntry {
+                name: "gone.jpg".to_string(),
+                path: PathBuf::from("does/not/exist.jpg"),
+            },
+            ArchiveEntry {
+                name: "Cargo.toml".to_string(),
+                path: PathBuf::from("Cargo.toml"),
+            },
+        ];
+        let chunks: Vec<io::Result<Bytes>> = zip_stream(entries).collect().await;
+        assert!(chunks.iter().all(|chunk| chunk.is_ok()));
+        let data: Vec<u8> = chunks
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>()
+            .concat();
+
+        assert!(!data.windows(8).any(|w| w == b"gone.jpg"));
+        assert!(data.windows(10).any(|w| w == b"Cargo.toml"));
+        assert!(data.windows(4).any(|w| w == b"PK\x05\x06"));
+    }
+}
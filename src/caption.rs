@@ -0,0 +1,320 @@
+use crate::format::source_format;
+use crate::image::ImageSvc;
+use crate::thumbnail::source_identity;
+use crate::Image;
+use std::collections::HashMap;
+use std::fs::File;
+use std::future::Future;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Start of an APP1 segment holding XMP rather than EXIF
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// Start of the APP13 segment holding Photoshop image resources
+const PHOTOSHOP_HEADER: &[u8] = b"Photoshop 3.0\0";
+/// Photoshop image resource wrapping the IPTC-IIM block
+const IPTC_RESOURCE: u16 = 0x0404;
+/// IPTC-IIM record 2, dataset 120: Caption/Abstract
+const IPTC_CAPTION: (u8, u8) = (2, 120);
+
+/// Identities of the files a caption was read from, missing ones included,
+/// so adding, editing or removing any of them is noticed
+type Stamp = Vec<Option<String>>;
+
+/// Captions by image path, shared by gallery pages and the search index. Each
+/// is read again only once a file it could come from changes, which costs a
+/// `stat` per file rather than reading sidecars and JPEG headers every time.
+#[derive(Default)]
+pub struct CaptionCache {
+    captions: Mutex<HashMap<String, (Stamp, Option<String>)>>,
+}
+
+impl CaptionCache {
+    pub async fn get(&self, image: &Image) -> Option<String> {
+        let filename = ImageSvc::get_image_filename(&image.path);
+        let sources = sidecars(&filename)
+            .into_iter()
+            .chain(jpegs(image).into_iter().map(PathBuf::from))
+            .collect();
+        self.get_or_read(&image.path, sources, read_caption(image))
+            .await
+    }
+
+    async fn get_or_read(
+        &self,
+        key: &str,
+        sources: Vec<PathBuf>,
+        read: impl Future<Output = Option<String>>,
+    ) -> Option<String> {
+        let stamp: Stamp = tokio::task::spawn_blocking(move || {
+            sources.iter().map(|path| source_identity(path)).collect()
+        })
+        .await
+        .unwrap_or_default();
+        if let Some((cached, caption)) = self.captions.lock().unwrap().get(key) {
+            if *cached == stamp {
+                return caption.clone();
+            }
+        }
+
+        let caption = read.await;
+        self.captions
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (stamp, caption.clone()));
+        caption
+    }
+}
+
+/// A photo's caption. A `.txt` sidecar beside the original wins over a
+/// caption embedded in its JPEG.
+async fn read_caption(image: &Image) -> Option<String> {
+    let filename = ImageSvc::get_image_filename(&image.path);
+    for sidecar in sidecars(&filename) {
+        if let Ok(text) = tokio::fs::read_to_string(sidecar).await {
+            if let Some(caption) = clean(&text) {
+                return Some(caption);
+            }
+        }
+    }
+
+    let jpegs = jpegs(image);
+    tokio::task::spawn_blocking(move || {
+        jpegs.iter().find_map(|jpeg| {
+            let file = File::open(jpeg).ok()?;
+            embedded_caption(BufReader::new(file))
+        })
+    })
+    .await
+    .ok()
+    .flatten()
+}
+
+/// The JPEG files of an image; a RAW file's caption is read from the JPEG
+/// it is paired with
+fn jpegs(image: &Image) -> Vec<String> {
+    image
+        .files
+        .iter()
+        .filter(|file| source_format(file).is_some_and(|format| format.name == "jpeg"))
+        .map(|file| ImageSvc::get_image_filename(file))
+        .collect()
+}
+
+/// `cat.jpg.txt`, then `cat.txt`
+fn sidecars(filename: &str) -> Vec<PathBuf> {
+    let path = Path::new(filename);
+    let mut sidecars = vec![PathBuf::from(format!("{filename}.txt"))];
+    // `index.txt` describes the folder, not `index.jpg`
+    if path.file_stem().is_some_and(|stem| stem != "index") {
+        sidecars.push(path.with_extension("txt"));
+    }
+    sidecars
+}
+
+fn clean(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+/// The XMP `dc:description` or IPTC Caption/Abstract of a JPEG, reading only
+/// the metadata segments before the image data
+fn embedded_caption(mut jpeg: impl Read) -> Option<String> {
+    let mut soi = [0u8; 2];
+    jpeg.read_exact(&mut soi).ok()?;
+    if soi != [0xFF, 0xD8] {
+        return None;
+    }
+
+    let (mut xmp, mut iptc) = (None, None);
+    while let Some((marker, segment)) = next_segment(&mut jpeg) {
+        match marker {
+            0xE1 if xmp.is_none() => {
+                xmp = segment.strip_prefix(XMP_HEADER).and_then(xmp_description);
+            }
+            0xED if iptc.is_none() => {
+                iptc = segment
+                    .strip_prefix(PHOTOSHOP_HEADER)
+                    .and_then(iptc_caption);
+            }
+            _ => {}
+        }
+    }
+    // editors keep both in step, but XMP holds Unicode without guesswork
+    xmp.or(iptc)
+}
+
+/// The next marker and its payload, until the image data starts
+fn next_segment(jpeg: &mut impl Read) -> Option<(u8, Vec<u8>)> {
+    let mut header = [0u8; 4];
+    jpeg.read_exact(&mut header).ok()?;
+    let [0xFF, marker, high, low] = header else {
+        return None;
+    };
+    // start of scan: everything after is pixels
+    if marker == 0xDA || marker == 0xD9 {
+        return None;
+    }
+    let len = usize::from(u16::from_be_bytes([high, low])).checked_sub(2)?;
+    let mut segment = vec![0; len];
+    jpeg.read_exact(&mut segment).ok()?;
+    Some((marker, segment))
+}
+
+/// Caption/Abstract from Photoshop image resources
+fn iptc_caption(mut resources: &[u8]) -> Option<String> {
+    while resources.len() >= 12 && resources.starts_with(b"8BIM") {
+        let id = u16::from_be_bytes([resources[4], resources[5]]);
+        // a Pascal string name, padded to an even length
+        let name_len = usize::from(resources[6]);
+        let size_at = 6 + (name_len + 2) / 2 * 2;
+        let size = u32::from_be_bytes(resources.get(size_at..size_at + 4)?.try_into().ok()?);
+        let data_at = size_at + 4;
+        let data = resources.get(data_at..data_at + size as usize)?;
+        if id == IPTC_RESOURCE {
+            return iptc_dataset(data, IPTC_CAPTION);
+        }
+        resources = resources.get(data_at + (size as usize).next_multiple_of(2)..)?;
+    }
+    None
+}
+
+/// The first `(record, dataset)` of an IPTC-IIM block
+fn iptc_dataset(mut iim: &[u8], wanted: (u8, u8)) -> Option<String> {
+    while iim.len() >= 5 && iim[0] == 0x1C {
+        let size = usize::from(u16::from_be_bytes([iim[3], iim[4]]));
+        // extended datasets are never captions
+        if size & 0x8000 != 0 {
+            return None;
+        }
+        let data = iim.get(5..5 + size)?;
+        if (iim[1], iim[2]) == wanted {
+            return clean(&String::from_utf8_lossy(data));
+        }
+        iim = &iim[5 + size..];
+    }
+    None
+}
+
+/// The first `dc:description` alternative of an XMP packet
+fn xmp_description(xmp: &[u8]) -> Option<String> {
+    let xmp = String::from_utf8_lossy(xmp);
+    let description = &xmp[xmp.find("<dc:description")?..];
+    let description = &description[..description.find("</dc:description>")?];
+    let item = &description[description.find("<rdf:li")?..];
+    let item = &item[item.find('>')? + 1..];
+    let item = &item[..item.find("</rdf:li>")?];
+    clean(&unescape(item))
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#xA;", "\n")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn segment(marker: u8, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let len = (header.len() + body.len() + 2) as u16;
+        let mut segment = vec![0xFF, marker];
+        segment.extend_from_slice(&len.to_be_bytes());
+        segment.extend_from_slice(header);
+        segment.extend_from_slice(body);
+        segment
+    }
+
+    fn iptc(caption: &str) -> Vec<u8> {
+        let mut iim = vec![0x1C, 2, 0, 0, 2, 0, 4];
+        iim.extend_from_slice(&[0x1C, 2, 120]);
+        iim.extend_from_slice(&(caption.len() as u16).to_be_bytes());
+        iim.extend_from_slice(caption.as_bytes());
+
+        let mut resources = b"8BIM".to_vec();
+        resources.extend_from_slice(&0x03EDu16.to_be_bytes());
+        resources.extend_from_slice(&[0, 0, 0, 0, 0, 2, 0, 0]);
+        resources.extend_from_slice(b"8BIM");
+        resources.extend_from_slice(&IPTC_RESOURCE.to_be_bytes());
+        resources.extend_from_slice(&[0, 0]);
+        resources.extend_from_slice(&(iim.len() as u32).to_be_bytes());
+        resources.extend_from_slice(&iim);
+        resources
+    }
+
+    fn jpeg(segments: &[Vec<u8>]) -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        segments.iter().for_each(|s| jpeg.extend_from_slice(s));
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0, 2, 0xAB, 0xCD]);
+        jpeg
+    }
+
+    const XMP: &str = r#"<x:xmpmeta><rdf:RDF><rdf:Description><dc:description><rdf:Alt>
+        <rdf:li xml:lang="x-default">Tom &amp; the ball</rdf:li>
+        </rdf:Alt></dc:description></rdf:Description></rdf:RDF></x:xmpmeta>"#;
+
+    #[test]
+    pub fn it_reads_iptc_captions() {
+        let jpeg = jpeg(&[
+            segment(0xE0, b"JFIF\0", &[1, 2]),
+            segment(0xED, PHOTOSHOP_HEADER, &iptc(" Cat on the sofa ")),
+        ]);
+        assert_eq!(
+            embedded_caption(jpeg.as_slice()),
+            Some("Cat on the sofa".to_string())
+        );
+    }
+
+    #[test]
+    pub fn it_prefers_xmp_descriptions() {
+        let jpeg = jpeg(&[
+            segment(0xED, PHOTOSHOP_HEADER, &iptc("Old caption")),
+            segment(0xE1, b"Exif\0\0", &[0; 8]),
+            segment(0xE1, XMP_HEADER, XMP.as_bytes()),
+        ]);
+        assert_eq!(
+            embedded_caption(jpeg.as_slice()),
+            Some("Tom & the ball".to_string())
+        );
+        assert_eq!(embedded_caption(&b"not a jpeg"[..]), None);
+    }
+
+    #[test]
+    pub fn it_looks_for_sidecars_beside_the_image() {
+        assert_eq!(
+            sidecars("/photos/Pets/cat.jpg"),
+            [
+                PathBuf::from("/photos/Pets/cat.jpg.txt"),
+                PathBuf::from("/photos/Pets/cat.txt")
+            ]
+        );
+        assert_eq!(
+            sidecars("/photos/Pets/index.jpg"),
+            [PathBuf::from("/photos/Pets/index.jpg.txt")]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn it_reads_captions_again_once_their_files_change() {
+        let dir = std::env::temp_dir().join(format!("caption-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let sidecar = dir.join("cat.txt");
+        std::fs::write(&sidecar, "first").unwrap();
+
+        let cache = CaptionCache::default();
+        let read = |text: &'static str| async move { Some(text.to_string()) };
+        let get = |text| cache.get_or_read("/cat.jpg", vec![sidecar.clone()], read(text));
+        assert_eq!(get("first").await.as_deref(), Some("first"));
+        // unchanged, so the cached caption is kept
+        assert_eq!(get("unread").await.as_deref(), Some("first"));
+
+        std::fs::write(&sidecar, "second caption").unwrap();
+        assert_eq!(get("second").await.as_deref(), Some("second"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,22 +1,36 @@
 use std::sync::Arc;
 
-use crate::{folder::FolderCache, image::ImageCache, pgp::AuthName};
+use crate::{
+    caption::CaptionCache, exif::ExifCache, folder::FolderCache, image::ImageCache, pgp::AuthName,
+    search::SearchIndex, worker::ThumbnailWorker,
+};
 
 #[derive(Clone)]
 pub struct GraphQLContext {
     pub folder_cache: Arc<FolderCache>,
     pub image_cache: Arc<ImageCache>,
+    pub exif_cache: Arc<ExifCache>,
+    /// Shared by gallery pages and the search index
+    pub caption_cache: Arc<CaptionCache>,
+    pub thumbnail_worker: Arc<ThumbnailWorker>,
+    pub search_index: Arc<SearchIndex>,
     pub auth: Option<AuthName>,
 }
 
 impl Default for GraphQLContext {
     fn default() -> Self {
         let folder_cache = Arc::new(FolderCache::default());
-        let image_cache = Arc::new(ImageCache::default());
+        let exif_cache = Arc::new(ExifCache::default());
+        let image_cache = Arc::new(ImageCache::new(exif_cache.clone()));
+        let caption_cache = Arc::new(CaptionCache::default());
 
         Self {
             folder_cache,
             image_cache,
+            exif_cache,
+            caption_cache: caption_cache.clone(),
+            thumbnail_worker: Arc::new(ThumbnailWorker::default()),
+            search_index: Arc::new(SearchIndex::new(caption_cache)),
             auth: None,
         }
     }
@@ -28,6 +42,10 @@ impl GraphQLContext {
             auth: auth.clone(),
             folder_cache: self.folder_cache.clone(),
             image_cache: self.image_cache.clone(),
+            exif_cache: self.exif_cache.clone(),
+            caption_cache: self.caption_cache.clone(),
+            thumbnail_worker: self.thumbnail_worker.clone(),
+            search_index: self.search_index.clone(),
         })
     }
 }
@@ -1,19 +1,91 @@
 use http::status::StatusCode;
 use leptos::*;
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 use thiserror::Error;
 
-#[derive(Clone, Debug, Error)]
+/// Errors shown to visitors. These cross the server function boundary as their
+/// `Display` text, so `FromStr` has to accept everything `Display` produces.
+#[derive(Clone, Debug, Error, PartialEq, Serialize, Deserialize)]
 pub enum AppError {
     #[error("Not Found")]
     NotFound,
+    #[error("Log in to view")]
+    LoginRequired,
+    #[error("Bad request: {0}")]
+    BadRequest(String),
+    #[error("Busy, try again shortly")]
+    Unavailable,
+    #[error("Server error: {0}")]
+    Internal(String),
 }
 
 impl AppError {
     pub fn status_code(&self) -> StatusCode {
         match self {
             AppError::NotFound => StatusCode::NOT_FOUND,
+            AppError::LoginRequired => StatusCode::FORBIDDEN,
+            AppError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            AppError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
+
+    /// What to tell the visitor, without internal detail
+    pub fn message(&self) -> &'static str {
+        match self {
+            AppError::NotFound => "Nothing here. It may have been moved or deleted.",
+            AppError::LoginRequired => "This folder is private. Log in to view it.",
+            AppError::BadRequest(_) => "That link doesn't look right.",
+            AppError::Unavailable => "The server is busy. Try again in a moment.",
+            AppError::Internal(_) => "Something went wrong on our end.",
+        }
+    }
+}
+
+impl FromStr for AppError {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "Not Found" => AppError::NotFound,
+            "Log in to view" => AppError::LoginRequired,
+            "Busy, try again shortly" => AppError::Unavailable,
+            s => match s.strip_prefix("Bad request: ") {
+                Some(cause) => AppError::BadRequest(cause.to_string()),
+                None => {
+                    AppError::Internal(s.strip_prefix("Server error: ").unwrap_or(s).to_string())
+                }
+            },
+        })
+    }
+}
+
+/// Anything the server function machinery itself fails with
+impl From<ServerFnError> for AppError {
+    fn from(e: ServerFnError) -> Self {
+        AppError::Internal(e.to_string())
+    }
+}
+
+impl From<ServerFnError<AppError>> for AppError {
+    fn from(e: ServerFnError<AppError>) -> Self {
+        match e {
+            ServerFnError::WrappedServerError(e) => e,
+            e => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+/// Wrap an error for a server function, also setting the HTTP status of the
+/// response it ends up in
+#[cfg(feature = "ssr")]
+pub fn server_error(e: impl Into<AppError>) -> ServerFnError<AppError> {
+    let e = e.into();
+    if let Some(response) = use_context::<leptos_axum::ResponseOptions>() {
+        response.set_status(e.status_code());
+    }
+    ServerFnError::WrappedServerError(e)
 }
 
 // A basic function to display errors served by the error boundaries.
@@ -36,7 +108,13 @@ pub fn ErrorTemplate(
     // Downcast lets us take a type that implements `std::error::Error`
     let errors: Vec<AppError> = errors
         .into_iter()
-        .filter_map(|(_k, v)| v.downcast_ref::<AppError>().cloned())
+        .filter_map(|(_k, v)| {
+            v.downcast_ref::<AppError>().cloned().or_else(|| {
+                v.downcast_ref::<ServerFnError<AppError>>()
+                    .cloned()
+                    .map(AppError::from)
+            })
+        })
         .collect();
     logging::log!("Errors: {errors:#?}");
 
@@ -46,8 +124,8 @@ pub fn ErrorTemplate(
     {
         use leptos_axum::ResponseOptions;
         let response = use_context::<ResponseOptions>();
-        if let Some(response) = response {
-            response.set_status(errors[0].status_code());
+        if let (Some(response), Some(error)) = (response, errors.first()) {
+            response.set_status(error.status_code());
         }
     }
 
@@ -60,11 +138,11 @@ pub fn ErrorTemplate(
             key=|(index, _error)| *index
             // renders each item to a view
             children=move |error| {
-                let error_string = error.1.to_string();
+                let error_string = error.1.message();
                 let error_code = error.1.status_code();
                 view! {
                     <h2>{error_code.to_string()}</h2>
-                    <p>"Error: " {error_string}</p>
+                    <p>{error_string}</p>
                 }
             }
         />
@@ -0,0 +1,252 @@
+use crate::image::{ImageError, ImageSvc};
+use crate::thumbnail::source_identity;
+use crate::ExifData;
+use cache_loader_async::backing::HashMapBacking;
+use cache_loader_async::cache_api::{CacheEntry, LoadingCache};
+use exif::{Exif, Field, In, Reader, Tag, Value};
+use image::DynamicImage;
+use log::*;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// An image path relative to `PHOTO_DIR`, with the `source_identity` of the
+/// original it was read from, so a re-exported photo is read afresh
+pub type ExifCacheKey = (String, Option<String>);
+
+pub type ExifCacheBacking = HashMapBacking<ExifCacheKey, CacheEntry<ExifData, ImageError>>;
+
+pub type ExifCacheData = LoadingCache<ExifCacheKey, ExifData, ImageError, ExifCacheBacking>;
+
+/// EXIF metadata keyed by image path and the identity of the original
+pub struct ExifCache {
+    pub cache: ExifCacheData,
+}
+
+impl Default for ExifCache {
+    fn default() -> Self {
+        let cache = LoadingCache::new(move |(path, _): ExifCacheKey| async move {
+            trace!("exif cache miss for {}", path);
+            let image_filename = ImageSvc::get_image_filename(&path);
+            tokio::task::spawn_blocking(move || read_exif(Path::new(&image_filename)))
+                .await
+                .map_err(|e| ImageError::FsError(e.to_string()))
+        });
+
+        Self { cache }
+    }
+}
+
+impl ExifCache {
+    pub async fn get(&self, path: &str) -> Result<ExifData, ImageError> {
+        let image_filename = ImageSvc::get_image_filename(path);
+        let identity =
+            tokio::task::spawn_blocking(move || source_identity(Path::new(&image_filename)))
+                .await
+                .map_err(|e| ImageError::FsError(e.to_string()))?;
+        self.cache
+            .get((path.to_string(), identity))
+            .await
+            .map_err(ImageError::from)
+    }
+}
+
+/// EXIF orientation of an original, 1 (upright) when missing or unreadable
+pub fn read_orientation(path: &Path) -> u32 {
+    let Ok(file) = File::open(path) else {
+        return 1;
+    };
+    let Ok(exif) = Reader::new().read_from_container(&mut BufReader::new(file)) else {
+        return 1;
+    };
+
+    exif.get_field(Tag::Orientation, In::PRIMARY)
+        .and_then(|f| f.value.get_uint(0))
+        .filter(|o| (1..=8).contains(o))
+        .unwrap_or(1)
+}
+
+fn field(exif: &Exif, tag: Tag) -> Option<&Field> {
+    exif.get_field(tag, In::PRIMARY)
+}
+
+fn ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &field(exif, tag)?.value {
+        Value::Ascii(values) => values
+            .first()
+            .map(|v| String::from_utf8_lossy(v).trim().to_string())
+            .filter(|v| !v.is_empty()),
+        _ => None,
+    }
+}
+
+fn rational(exif: &Exif, tag: Tag, index: usize) -> Option<f64> {
+    match &field(exif, tag)?.value {
+        Value::Rational(values) => values.get(index).map(|r| r.to_f64()),
+        Value::SRational(values) => values.get(index).map(|r| r.to_f64()),
+        _ => None,
+    }
+    .filter(|v| v.is_finite())
+}
+
+fn uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    field(exif, tag)?.value.get_uint(0)
+}
+
+/// `2024:05:01 12:34:56` to `2024-05-01T12:34:56`, which sorts as a string
+fn capture_time(exif: &Exif) -> Option<String> {
+    let raw = ascii(exif, Tag::DateTimeOriginal).or_else(|| ascii(exif, Tag::DateTime))?;
+    let (date, time) = raw.split_once(' ')?;
+    if date.len() != 10 || date.starts_with("0000") {
+        return None;
+    }
+
+    Some(format!("{}T{}", date.replace(':', "-"), time))
+}
+
+fn camera(exif: &Exif) -> Option<String> {
+    let make = ascii(exif, Tag::Make);
+    let model = ascii(exif, Tag::Model);
+    match (make, model) {
+        (Some(make), Some(model)) if model.starts_with(&make) => Some(model),
+        (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+        (make, model) => make.or(model),
+    }
+}
+
+fn shutter(exif: &Exif) -> Option<String> {
+    let seconds = rational(exif, Tag::ExposureTime, 0)?;
+    if seconds <= 0.0 {
+        None
+    } else if seconds < 1.0 {
+        Some(format!("1/{}", (1.0 / seconds).round()))
+    } else {
+        Some(format!("{}s", seconds))
+    }
+}
+
+fn coordinate(exif: &Exif, tag: Tag, reference: Tag, negative: &str) -> Option<f64> {
+    let degrees = rational(exif, tag, 0)?;
+    let minutes = rational(exif, tag, 1).unwrap_or_default();
+    let seconds = rational(exif, tag, 2).unwrap_or_default();
+    let value = degrees + minutes / 60.0 + seconds / 3600.0;
+
+    match ascii(exif, reference) {
+        Some(r) if r.eq_ignore_ascii_case(negative) => Some(-value),
+        _ => Some(value),
+    }
+}
+
+/// Read capture and camera metadata from an original; pixel dimensions fall
+/// back to the image header when the file has no usable EXIF block
+pub fn read_exif(path: &Path) -> ExifData {
+    let exif = File::open(path).ok().and_then(|f| {
+        Reader::new()
+            .read_from_container(&mut BufReader::new(f))
+            .ok()
+    });
+
+    let mut data = match exif {
+        Some(exif) => ExifData {
+            captured_at: capture_time(&exif),
+            camera: camera(&exif),
+            lens: ascii(&exif, Tag::LensModel),
+            focal_length: rational(&exif, Tag::FocalLength, 0),
+            aperture: rational(&exif, Tag::FNumber, 0),
+            shutter: shutter(&exif),
+            iso: uint(&exif, Tag::PhotographicSensitivity),
+            latitude: coordinate(&exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, "S"),
+            longitude: coordinate(&exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, "W"),
+            width: uint(&exif, Tag::PixelXDimension).or_else(|| uint(&exif, Tag::ImageWidth)),
+            height: uint(&exif, Tag::PixelYDimension).or_else(|| uint(&exif, Tag::ImageLength)),
+            orientation: uint(&exif, Tag::Orientation).filter(|o| (1..=8).contains(o)),
+        },
+        None => ExifData::default(),
+    };
+
+    if data.width.is_none() || data.height.is_none() {
+        if let Ok((width, height)) = image::image_dimensions(path) {
+            data.width = Some(width);
+            data.height = Some(height);
+        }
+    }
+
+    data
+}
+
+/// Width and height as displayed; orientations 5 to 8 turn the image on its side
+pub fn display_size(data: &ExifData) -> Option<(u32, u32)> {
+    let (width, height) = (data.width?, data.height?);
+    if (5..=8).contains(&data.orientation.unwrap_or(1)) {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+/// Rotate and flip decoded pixels so they display upright
+pub fn apply_orientation(img: DynamicImage, orientation: u32) -> DynamicImage {
+    match orientation {
+        2 => img.fliph(),
+        3 => img.rotate180(),
+        4 => img.flipv(),
+        5 => img.rotate90().fliph(),
+        6 => img.rotate90(),
+        7 => img.rotate270().fliph(),
+        8 => img.rotate270(),
+        _ => img,
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    pub fn it_swaps_axes_for_rotated_orientations() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 30));
+        for orientation in 5..=8 {
+            let rotated = apply_orientation(img.clone(), orientation);
+            assert_eq!((rotated.width(), rotated.height()), (30, 40));
+        }
+    }
+
+    #[test]
+    pub fn it_keeps_axes_for_upright_orientations() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(40, 30));
+        for orientation in 1..=4 {
+            let rotated = apply_orientation(img.clone(), orientation);
+            assert_eq!((rotated.width(), rotated.height()), (40, 30));
+        }
+    }
+
+    #[test]
+    pub fn it_swaps_display_size_for_rotated_orientations() {
+        let data = ExifData {
+            width: Some(6000),
+            height: Some(4000),
+            ..Default::default()
+        };
+        assert_eq!(display_size(&data), Some((6000, 4000)));
+        let rotated = ExifData {
+            orientation: Some(6),
+            ..data
+        };
+        assert_eq!(display_size(&rotated), Some((4000, 6000)));
+        assert_eq!(display_size(&ExifData::default()), None);
+    }
+
+    #[test]
+    pub fn it_reads_nothing_from_missing_files() {
+        assert_eq!(
+            read_exif(Path::new("/does/not/exist.jpg")),
+            ExifData::default()
+        );
+    }
+
+    #[test]
+    pub fn it_defaults_to_upright_without_exif() {
+        assert_eq!(read_orientation(Path::new("/does/not/exist.jpg")), 1);
+    }
+}
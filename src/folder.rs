@@ -1,9 +1,12 @@
 use crate::context::GraphQLContext;
+use crate::error_template::AppError;
 use crate::pgp::AuthName;
 use crate::Folder;
 use crate::{base_folder, image::ImageSvc};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
 use cache_loader_async::backing::HashMapBacking;
-use cache_loader_async::cache_api::{CacheEntry, LoadingCache};
+use cache_loader_async::cache_api::{CacheEntry, CacheLoadingError, LoadingCache};
 use futures::stream::{self, StreamExt};
 use log::*;
 use std::path::Path;
@@ -50,17 +53,69 @@ impl Folder {
 
 #[derive(Debug, Clone)]
 pub enum FolderError {
-    CacheError,
+    CacheError(String),
+    /// The caller may not see this folder
     NotAllowed,
-    FsError,
+    NotFound(String),
+    /// A malformed request, such as a path escaping `PHOTO_DIR`
+    Invalid(String),
+    FsError(String),
 }
 impl std::error::Error for FolderError {}
 impl fmt::Display for FolderError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             FolderError::NotAllowed => write!(f, "Operation not allowed"),
-            FolderError::FsError => write!(f, "Fs error"),
-            FolderError::CacheError => write!(f, "Cache error"),
+            FolderError::NotFound(path) => write!(f, "Not found: {path}"),
+            FolderError::Invalid(cause) => write!(f, "Invalid request: {cause}"),
+            FolderError::FsError(cause) => write!(f, "Fs error: {cause}"),
+            FolderError::CacheError(cause) => write!(f, "Cache error: {cause}"),
+        }
+    }
+}
+
+impl FolderError {
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            FolderError::NotAllowed => StatusCode::FORBIDDEN,
+            FolderError::NotFound(_) => StatusCode::NOT_FOUND,
+            FolderError::Invalid(_) => StatusCode::BAD_REQUEST,
+            FolderError::FsError(_) | FolderError::CacheError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+impl From<CacheLoadingError<FolderError>> for FolderError {
+    fn from(e: CacheLoadingError<FolderError>) -> Self {
+        match e {
+            CacheLoadingError::LoadingError(e) => e,
+            e => FolderError::CacheError(e.to_string()),
+        }
+    }
+}
+
+impl From<FolderError> for AppError {
+    fn from(e: FolderError) -> Self {
+        match e {
+            FolderError::NotAllowed => AppError::LoginRequired,
+            FolderError::NotFound(_) => AppError::NotFound,
+            FolderError::Invalid(cause) => AppError::BadRequest(cause),
+            e => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+/// Client errors explain themselves; server errors are logged and kept vague
+impl IntoResponse for FolderError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        if status.is_server_error() {
+            error!("{self}");
+            (status, "Internal server error").into_response()
+        } else {
+            (status, self.to_string()).into_response()
         }
     }
 }
@@ -96,21 +151,27 @@ impl FolderSvc {
             .await
             .map_err(|e| {
                 println!("Error loading from cache: {:#?}", e);
-                FolderError::CacheError
+                FolderError::from(e)
             })
     }
 
-    async fn list_internal(
+    /// A folder's subfolders straight from disk, bypassing the cache
+    pub(crate) async fn list_internal(
         folder: &str,
         auth_type: Option<AuthName>,
     ) -> Result<Vec<Folder>, FolderError> {
         info!("Checking folders for {folder}");
         if folder.contains("..") {
             println!("Folder cannot contain '..'");
-            return Err(FolderError::NotAllowed);
+            return Err(FolderError::Invalid(
+                "path may not contain '..'".to_string(),
+            ));
         }
 
-        let paths_res = fs::read_dir(base_folder() + folder).map_err(|_| FolderError::FsError)?;
+        let paths_res = fs::read_dir(base_folder() + folder).map_err(|e| match e.kind() {
+            std::io::ErrorKind::NotFound => FolderError::NotFound(folder.to_string()),
+            _ => FolderError::FsError(format!("{folder}: {e}")),
+        })?;
         Ok(stream::iter(
             paths_res
                 .into_iter()
@@ -173,6 +234,16 @@ pub mod tests {
         dotenvy::from_filename(".env.test").ok();
         let context = GraphQLContext::default();
         let result = FolderSvc::list(&context, "/../test").await;
-        assert!(result.is_err());
+        assert!(matches!(result, Err(FolderError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    pub async fn it_reports_missing_directories_as_not_found() {
+        dotenvy::from_filename(".env.test").ok();
+        let context = GraphQLContext::default();
+        let result = FolderSvc::list(&context, "/does-not-exist").await;
+        let error = result.unwrap_err();
+        assert_eq!(error.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(AppError::from(error), AppError::NotFound);
     }
 }
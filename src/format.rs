@@ -0,0 +1,283 @@
+use crate::get_env;
+use crate::image::ImageError;
+use crate::raw;
+use crate::EndsWithAny;
+use image::{DynamicImage, ImageError as ImgError, ImageOutputFormat};
+use lazy_static::lazy_static;
+use log::*;
+use std::io::Cursor;
+use std::path::Path;
+use webp::{Encoder, WebPMemory};
+
+/// Decodes an original on disk into pixels for thumbnailing
+pub type SourceLoader = fn(&Path) -> Result<DynamicImage, ImgError>;
+
+/// A kind of original file the gallery knows how to list and thumbnail
+pub struct SourceFormat {
+    pub name: &'static str,
+    pub extensions: &'static [&'static str],
+    pub loader: SourceLoader,
+    /// Camera RAW containers are paired with a same-named JPEG when listing
+    pub raw: bool,
+}
+
+impl SourceFormat {
+    pub fn matches(&self, filename: &str) -> bool {
+        filename.to_lowercase().as_str().ends_with_any(self.extensions)
+    }
+
+    pub fn load(&self, path: &Path) -> Result<DynamicImage, ImgError> {
+        (self.loader)(path)
+    }
+}
+
+fn open_image(path: &Path) -> Result<DynamicImage, ImgError> {
+    image::open(path)
+}
+
+pub static KNOWN_FORMATS: &[SourceFormat] = &[
+    SourceFormat {
+        name: "jpeg",
+        extensions: &[".jpg", ".jpeg"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "png",
+        extensions: &[".png"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "webp",
+        extensions: &[".webp"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "tiff",
+        extensions: &[".tif", ".tiff"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "gif",
+        extensions: &[".gif"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "avif",
+        extensions: &[".avif"],
+        loader: open_image,
+        raw: false,
+    },
+    SourceFormat {
+        name: "raw",
+        extensions: &[".nef", ".cr2", ".arw", ".dng"],
+        loader: raw::load_preview,
+        raw: true,
+    },
+];
+
+#[cfg(feature = "avif-decoder")]
+const DEFAULT_FORMATS: &str = "jpeg,png,webp,tiff,gif,avif,raw";
+#[cfg(not(feature = "avif-decoder"))]
+const DEFAULT_FORMATS: &str = "jpeg,png,webp,tiff,gif,raw";
+
+lazy_static! {
+    static ref SOURCE_FORMATS: Vec<&'static SourceFormat> =
+        parse_formats(&get_env("IMAGE_FORMATS", DEFAULT_FORMATS));
+}
+
+/// Resolve a comma separated list of format names, skipping unknown ones
+fn parse_formats(names: &str) -> Vec<&'static SourceFormat> {
+    names
+        .split(',')
+        .map(|n| n.trim().to_lowercase())
+        .filter(|n| !n.is_empty())
+        .filter_map(|n| {
+            let format = KNOWN_FORMATS.iter().find(|f| f.name == n);
+            if format.is_none() {
+                warn!("Ignoring unknown image format '{n}'");
+            }
+            format
+        })
+        .collect()
+}
+
+/// The enabled source format for a file, if any
+pub fn source_format(filename: &str) -> Option<&'static SourceFormat> {
+    SOURCE_FORMATS.iter().find(|f| f.matches(filename)).copied()
+}
+
+pub fn is_source_image(filename: &str) -> bool {
+    source_format(filename).is_some()
+}
+
+pub fn is_raw(filename: &str) -> bool {
+    source_format(filename).is_some_and(|f| f.raw)
+}
+
+const THUMB_QUALITY: u8 = 82;
+// AVIF reaches comparable fidelity at a lower nominal quality
+const AVIF_QUALITY: f32 = 60.0;
+const AVIF_SPEED: u8 = 8;
+
+/// Encoding a thumbnail is sent in, picked from the request's `Accept` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ThumbFormat {
+    Avif,
+    Webp,
+    Jpeg,
+}
+
+impl ThumbFormat {
+    /// Formats rendered ahead of time; JPEG is only produced on request
+    pub const PREGENERATED: [ThumbFormat; 2] = [ThumbFormat::Avif, ThumbFormat::Webp];
+
+    /// Prefer AVIF, then WebP, when the client lists them explicitly; a bare
+    /// `*/*` or missing header gets JPEG, which every client can show
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let accepted = |media_type: &str| {
+            accept.unwrap_or_default().split(',').any(|range| {
+                let mut params = range.split(';').map(str::trim);
+                let matches = params
+                    .next()
+                    .is_some_and(|t| t.eq_ignore_ascii_case(media_type));
+                let refused = params.any(|p| {
+                    p.strip_prefix("q=")
+                        .and_then(|q| q.parse::<f32>().ok())
+                        .is_some_and(|q| q <= 0.0)
+                });
+                matches && !refused
+            })
+        };
+
+        if accepted("image/avif") {
+            ThumbFormat::Avif
+        } else if accepted("image/webp") {
+            ThumbFormat::Webp
+        } else {
+            ThumbFormat::Jpeg
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbFormat::Avif => "avif",
+            ThumbFormat::Webp => "webp",
+            ThumbFormat::Jpeg => "jpg",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ThumbFormat::Avif => "image/avif",
+            ThumbFormat::Webp => "image/webp",
+            ThumbFormat::Jpeg => "image/jpeg",
+        }
+    }
+
+    pub fn encode(&self, img: &DynamicImage) -> Result<Vec<u8>, ImageError> {
+        match self {
+            ThumbFormat::Avif => {
+                let rgb = img.to_rgb8();
+                let pixels: Vec<ravif::RGB8> = rgb
+                    .pixels()
+                    .map(|p| ravif::RGB8::new(p[0], p[1], p[2]))
+                    .collect();
+                let encoded = ravif::Encoder::new()
+                    .with_quality(AVIF_QUALITY)
+                    .with_speed(AVIF_SPEED)
+                    .encode_rgb(ravif::Img::new(
+                        &pixels[..],
+                        rgb.width() as usize,
+                        rgb.height() as usize,
+                    ))
+                    .map_err(|e| ImageError::ThumbError(e.to_string()))?;
+                Ok(encoded.avif_file)
+            }
+            ThumbFormat::Webp => {
+                let rgb = DynamicImage::ImageRgb8(img.to_rgb8());
+                let encoder: Encoder =
+                    Encoder::from_image(&rgb).map_err(|e| ImageError::ThumbError(e.to_string()))?;
+                let webp: WebPMemory = encoder.encode(THUMB_QUALITY as f32);
+                Ok(webp.to_vec())
+            }
+            ThumbFormat::Jpeg => {
+                let mut data = Cursor::new(vec![]);
+                DynamicImage::ImageRgb8(img.to_rgb8())
+                    .write_to(&mut data, ImageOutputFormat::Jpeg(THUMB_QUALITY))
+                    .map_err(|e| ImageError::ThumbError(e.to_string()))?;
+                Ok(data.into_inner())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::RgbImage;
+
+    #[test]
+    pub fn it_negotiates_thumb_formats() {
+        let chrome = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+        let old_safari = "image/webp,image/png,image/svg+xml,image/*;q=0.8,*/*;q=0.5";
+        assert_eq!(ThumbFormat::from_accept(Some(chrome)), ThumbFormat::Avif);
+        assert_eq!(
+            ThumbFormat::from_accept(Some(old_safari)),
+            ThumbFormat::Webp
+        );
+        assert_eq!(ThumbFormat::from_accept(Some("*/*")), ThumbFormat::Jpeg);
+        assert_eq!(ThumbFormat::from_accept(None), ThumbFormat::Jpeg);
+        assert_eq!(
+            ThumbFormat::from_accept(Some("image/avif;q=0, image/webp")),
+            ThumbFormat::Webp
+        );
+    }
+
+    #[test]
+    pub fn it_encodes_every_thumb_format() {
+        let img = DynamicImage::ImageRgb8(RgbImage::new(16, 12));
+        for format in [ThumbFormat::Avif, ThumbFormat::Webp, ThumbFormat::Jpeg] {
+            let data = format.encode(&img).unwrap();
+            let decoded_format = image::guess_format(&data);
+            match format {
+                ThumbFormat::Webp => assert_eq!(decoded_format.unwrap(), image::ImageFormat::WebP),
+                ThumbFormat::Jpeg => assert_eq!(decoded_format.unwrap(), image::ImageFormat::Jpeg),
+                ThumbFormat::Avif => assert!(data.len() > 16),
+            }
+        }
+    }
+
+    #[test]
+    pub fn it_parses_configured_formats() {
+        let formats = parse_formats("jpeg, PNG,,bogus");
+        let names: Vec<&str> = formats.iter().map(|f| f.name).collect();
+        assert_eq!(names, vec!["jpeg", "png"]);
+    }
+
+    #[test]
+    pub fn it_matches_extensions_case_insensitively() {
+        let format = parse_formats("tiff")[0];
+        assert!(format.matches("/Scans/IMG_0001.TIF"));
+        assert!(format.matches("scan.tiff"));
+        assert!(!format.matches("scan.jpg"));
+    }
+
+    #[test]
+    pub fn it_recognises_default_formats() {
+        assert!(is_source_image("/Pets/D75_0360.jpg"));
+        assert!(is_source_image("/export.png"));
+        assert!(!is_source_image("/index.txt"));
+    }
+
+    #[test]
+    pub fn it_recognises_raw_formats() {
+        assert!(is_raw("/Shoot/DSC_0001.NEF"));
+        assert!(is_raw("/Shoot/IMG_0001.cr2"));
+        assert!(!is_raw("/Shoot/DSC_0001.jpg"));
+    }
+}
@@ -1,31 +1,52 @@
 #![allow(clippy::unnecessary_unwrap, clippy::needless_return)]
+use crate::archive::ArchiveEntry;
+use crate::caption::CaptionCache;
 use crate::context::GraphQLContext;
-use crate::folder::FolderSvc;
+use crate::error_template::AppError;
+use crate::exif::{apply_orientation, display_size, read_orientation, ExifCache};
+use crate::folder::{FolderError, FolderSvc};
+use crate::format::{is_raw, is_source_image, source_format, ThumbFormat};
 use crate::pgp::AuthName;
-use crate::EndsWithAny;
+use crate::placeholder;
+use crate::queue::{run_job, Priority};
+use crate::settings::FolderSettings;
+use crate::sort::SortOrder;
+use crate::thumbnail::{
+    coalesce, folder_thumb_folder, single_flight, source_identity, thumb_folder, thumb_sizes,
+    write_atomic, PLACEHOLDER_EXTENSION, PLACEHOLDER_SIZE,
+};
 use crate::Folder;
-use crate::{base_folder, Image};
+use crate::{base_folder, ExifData, Image, ImagePage};
 use async_recursion::async_recursion;
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use base64::{engine::general_purpose, Engine as _};
 use cache_loader_async::backing::HashMapBacking;
-use cache_loader_async::cache_api::{CacheEntry, LoadingCache};
-use image::imageops::FilterType;
-use image::{DynamicImage, ImageError as ImgError};
+use cache_loader_async::cache_api::{CacheEntry, CacheLoadingError, LoadingCache};
+use futures::stream::{self, StreamExt};
+use image::imageops::{self, FilterType};
+use image::{DynamicImage, ImageError as ImgError, RgbImage};
 use log::*;
-use rayon::prelude::*;
-use std::{fmt, fs, path::Path};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::UNIX_EPOCH;
+use std::{fmt, fs, io, path::Path};
 use tokio::io::AsyncReadExt;
-use tokio::io::AsyncWriteExt;
-use webp::{Encoder, WebPMemory};
 
 pub struct ImageCache {
     pub cache: ImageCacheData,
 }
 
-impl Default for ImageCache {
-    fn default() -> Self {
-        let cache = LoadingCache::new(move |key: ImageCacheKey| async move {
-            warn!("key path is {}", key.path);
-            ImageSvc::list_internal(&key.path, &key.auth_type).await
+impl ImageCache {
+    pub fn new(exif_cache: Arc<ExifCache>) -> Self {
+        let cache = LoadingCache::new(move |key: ImageCacheKey| {
+            let exif_cache = exif_cache.clone();
+            async move {
+                warn!("key path is {}", key.path);
+                let images = ImageSvc::list_internal(&key.path, &key.auth_type).await?;
+                Ok(ImageSvc::attach_exif(&exif_cache, images).await)
+            }
         });
 
         Self { cache }
@@ -34,7 +55,17 @@ impl Default for ImageCache {
 
 impl Image {
     pub fn new(path: String) -> Self {
-        Self { path }
+        Self {
+            files: vec![path.clone()],
+            path,
+            exif: None,
+            modified: None,
+            placeholder: None,
+            width: None,
+            height: None,
+            aspect_ratio: None,
+            caption: None,
+        }
     }
 }
 
@@ -44,6 +75,44 @@ impl Image {
     }
 }
 
+/// The most images `list_after` returns at once
+const MAX_SLICE: usize = 200;
+
+/// The last image a cursor-paged client was sent, and its position as a
+/// fallback for when that image has since been removed
+#[derive(Debug, Clone, PartialEq)]
+struct Cursor {
+    offset: usize,
+    path: String,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        general_purpose::URL_SAFE_NO_PAD.encode(format!("{}:{}", self.offset, self.path))
+    }
+
+    fn decode(token: &str) -> Option<Self> {
+        let decoded = general_purpose::URL_SAFE_NO_PAD.decode(token).ok()?;
+        let decoded = String::from_utf8(decoded).ok()?;
+        let (offset, path) = decoded.split_once(':')?;
+        Some(Self {
+            offset: offset.parse().ok()?,
+            path: path.to_string(),
+        })
+    }
+
+    /// Where to carry on from in `images`
+    fn resume(&self, images: &[Image]) -> usize {
+        images
+            .iter()
+            .position(|image| image.path == self.path)
+            .map(|position| position + 1)
+            // what followed it has moved up into its place
+            .unwrap_or(self.offset)
+            .min(images.len())
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Clone, Hash)]
 pub struct ImageCacheKey {
     path: String,
@@ -56,19 +125,110 @@ pub type ImageCacheData = LoadingCache<ImageCacheKey, Vec<Image>, ImageError, Im
 
 #[derive(Debug, Clone)]
 pub enum ImageError {
+    /// The caller may not see this folder or file
     NotAllowed,
-    ThumbError,
-    FsError,
-    CacheError,
+    NotFound(String),
+    /// A malformed request, such as a path escaping `PHOTO_DIR`
+    Invalid(String),
+    ThumbError(String),
+    FsError(String),
+    CacheError(String),
+    /// The thumbnail queue is full; try again shortly
+    Busy,
 }
 impl std::error::Error for ImageError {}
 impl fmt::Display for ImageError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ImageError::NotAllowed => write!(f, "Operation not allowed"),
-            ImageError::ThumbError => write!(f, "Could not generate thumbnail"),
-            ImageError::FsError => write!(f, "Fs error"),
-            ImageError::CacheError => write!(f, "Cache error"),
+            ImageError::NotFound(path) => write!(f, "Not found: {path}"),
+            ImageError::Invalid(cause) => write!(f, "Invalid request: {cause}"),
+            ImageError::ThumbError(cause) => write!(f, "Could not generate thumbnail: {cause}"),
+            ImageError::FsError(cause) => write!(f, "Fs error: {cause}"),
+            ImageError::CacheError(cause) => write!(f, "Cache error: {cause}"),
+            ImageError::Busy => write!(f, "Thumbnail queue is full"),
+        }
+    }
+}
+
+impl ImageError {
+    /// A failed filesystem operation on `path`; missing files become `NotFound`
+    pub fn io(path: impl fmt::Display, e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => ImageError::NotFound(path.to_string()),
+            _ => ImageError::FsError(format!("{path}: {e}")),
+        }
+    }
+
+    /// A failed decode of `path`; a missing or unreadable file is reported as
+    /// by `io`, anything else as a `ThumbError`
+    pub fn decode(path: impl fmt::Display, e: ImgError) -> Self {
+        match e {
+            ImgError::IoError(e) => Self::io(path, e),
+            e => ImageError::ThumbError(format!("{path}: {e}")),
+        }
+    }
+
+    pub fn status_code(&self) -> StatusCode {
+        match self {
+            ImageError::NotAllowed => StatusCode::FORBIDDEN,
+            ImageError::NotFound(_) => StatusCode::NOT_FOUND,
+            ImageError::Invalid(_) => StatusCode::BAD_REQUEST,
+            ImageError::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            ImageError::ThumbError(_) | ImageError::FsError(_) | ImageError::CacheError(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+}
+
+/// Errors from the image cache's loader come back as they were
+impl From<CacheLoadingError<ImageError>> for ImageError {
+    fn from(e: CacheLoadingError<ImageError>) -> Self {
+        match e {
+            CacheLoadingError::LoadingError(e) => e,
+            e => ImageError::CacheError(e.to_string()),
+        }
+    }
+}
+
+impl From<FolderError> for ImageError {
+    fn from(e: FolderError) -> Self {
+        match e {
+            FolderError::NotAllowed => ImageError::NotAllowed,
+            FolderError::NotFound(path) => ImageError::NotFound(path),
+            FolderError::Invalid(cause) => ImageError::Invalid(cause),
+            FolderError::FsError(cause) => ImageError::FsError(cause),
+            FolderError::CacheError(cause) => ImageError::CacheError(cause),
+        }
+    }
+}
+
+impl From<ImageError> for AppError {
+    fn from(e: ImageError) -> Self {
+        match e {
+            ImageError::NotAllowed => AppError::LoginRequired,
+            ImageError::NotFound(_) => AppError::NotFound,
+            ImageError::Invalid(cause) => AppError::BadRequest(cause),
+            ImageError::Busy => AppError::Unavailable,
+            e => AppError::Internal(e.to_string()),
+        }
+    }
+}
+
+/// Client errors explain themselves; server errors are logged and kept vague
+impl IntoResponse for ImageError {
+    fn into_response(self) -> Response {
+        let status = self.status_code();
+        match self {
+            ImageError::Busy => {
+                (status, [(header::RETRY_AFTER, "1")], self.to_string()).into_response()
+            }
+            e if status.is_server_error() => {
+                error!("{e}");
+                (status, "Internal server error").into_response()
+            }
+            e => (status, e.to_string()).into_response(),
         }
     }
 }
@@ -97,6 +257,14 @@ fn strip_slashes(filename: &str) -> &str {
     filename
 }
 
+/// A rendered thumbnail and the file it was read from, whose name and
+/// modification time let clients revalidate it without the bytes being hashed
+#[derive(Debug, Clone)]
+pub struct Thumbnail {
+    pub data: Vec<u8>,
+    pub path: PathBuf,
+}
+
 #[derive(Clone)]
 pub struct ImageSvc {}
 
@@ -117,26 +285,28 @@ impl ImageSvc {
             return false;
         }
 
-        // hidden exists, and no auth passed, hidden
-        if auth_type.is_none() {
-            return true;
-        }
-
-        let auth_type = auth_type.as_ref().unwrap();
+        // only named logins need the list of who may see it
+        let file_contents = match auth_type {
+            Some(auth) if !auth.name.is_empty() && auth.name != "super" => {
+                tokio::fs::read_to_string(&folder).await.unwrap_or_default()
+            }
+            _ => String::new(),
+        };
 
-        if auth_type.name.eq("super") {
-            return false;
-        }
+        !Self::hide_allows(&file_contents, auth_type)
+    }
 
-        if auth_type.name.is_empty() {
-            return true;
+    /// Whether a `.hide` file listing `file_contents` lets `auth_type` in;
+    /// `super` sees everything and anonymous visitors nothing
+    pub fn hide_allows(file_contents: &str, auth_type: &Option<AuthName>) -> bool {
+        match auth_type {
+            None => false,
+            Some(auth) if auth.name.eq("super") => true,
+            Some(auth) if auth.name.is_empty() => false,
+            Some(auth) => file_contents
+                .split('\n')
+                .any(|l| l.trim().eq(auth.name.trim())),
         }
-
-        let file_contents = tokio::fs::read_to_string(&folder).await.unwrap();
-
-        !file_contents
-            .split('\n')
-            .any(|l| l.trim().eq(auth_type.name.trim()))
     }
 
     pub async fn list(context: &GraphQLContext, folder: &str) -> Result<Vec<Image>, ImageError> {
@@ -148,53 +318,57 @@ impl ImageSvc {
                 auth_type: context.auth.clone(),
             })
             .await
-            .map_err(|_| ImageError::CacheError)
+            .map_err(ImageError::from)
     }
 
-    pub async fn generate_images_for_folder(
-        context: &GraphQLContext,
-        folder: &str,
-        auth_type: &Option<AuthName>,
-    ) -> Result<Vec<Image>, ImageError> {
-        let images = context
-            .image_cache
-            .cache
-            .get(ImageCacheKey {
-                path: folder.to_string(),
-                auth_type: auth_type.clone(),
+    /// Decode an image for pre-generation and write its placeholder, if any
+    /// allowed size is still missing in the pre-generated formats
+    pub fn prepare_pregeneration(filename: &str) -> Result<Option<Pregeneration>, ImageError> {
+        Self::ensure_thumb_dir(filename);
+        let (orientation, identity) = Self::source_key(filename);
+        let missing: Vec<(u32, ThumbFormat)> = thumb_sizes()
+            .into_iter()
+            .flat_map(|size| ThumbFormat::PREGENERATED.map(|format| (size, format)))
+            .filter(|(size, format)| {
+                let thumb_filename = Self::get_keyed_thumb_filename(
+                    filename,
+                    *size,
+                    orientation,
+                    identity.as_deref(),
+                    *format,
+                );
+                !Path::new(&thumb_filename).exists()
             })
-            .await
-            .map_err(|_| ImageError::CacheError)?;
-
-        let inner_images = images.clone();
-        tokio::task::spawn_blocking(move || {
-            if let Some(image) = inner_images.first() {
-                let thumb_directory = Self::get_thumb_dirname(&image.path);
-                let thumb_directory = Path::new(&thumb_directory);
-                if !thumb_directory.exists() {
-                    fs::create_dir(thumb_directory).unwrap();
-                }
-                inner_images.par_iter().for_each(|file| {
-                    let res =
-                        ImageSvc::generate_thumbnails(&file.path, vec![150, 300, 600, 1200, 2400]);
-                    if res.is_err() {
-                        println!("Could not generate thumbnails for {}", &file.path);
-                    }
-                });
-            }
-        });
+            .collect();
+        let placeholder_filename = Self::get_placeholder_filename(filename, identity.as_deref());
+        let placeholder_missing = !Path::new(&placeholder_filename).exists();
+        if missing.is_empty() && !placeholder_missing {
+            return Ok(None);
+        }
 
-        Ok(images)
+        let img = Self::open_source(filename).map_err(|e| ImageError::decode(filename, e))?;
+        let img = apply_orientation(img, orientation);
+        if placeholder_missing {
+            Self::write_placeholder(&placeholder_filename, &img);
+        }
+        Ok(Some(Pregeneration {
+            filename: filename.to_string(),
+            orientation,
+            identity,
+            image: Arc::new(img.into_rgb8()),
+            missing,
+        }))
     }
 
-    async fn list_internal(
+    /// A folder's images straight from disk, bypassing the cache
+    pub(crate) async fn list_internal(
         folder: &str,
         auth_type: &Option<AuthName>,
     ) -> Result<Vec<Image>, ImageError> {
         let folder = folder.strip_prefix("/").unwrap();
         if folder.contains("..") {
             eprintln!("Attempt to traverse upward");
-            return Err(ImageError::NotAllowed);
+            return Err(ImageError::Invalid("path may not contain '..'".to_string()));
         }
 
         if Self::is_hidden(folder, auth_type).await {
@@ -202,123 +376,571 @@ impl ImageSvc {
             return Err(ImageError::NotAllowed);
         }
 
-        let paths_res = fs::read_dir(base_folder() + folder).map_err(|_| ImageError::FsError)?;
-        let mut paths: Vec<Image> = paths_res
+        let paths_res =
+            fs::read_dir(base_folder() + folder).map_err(|e| ImageError::io(folder, e))?;
+        let paths: Vec<String> = paths_res
             .into_iter()
             .filter(|f| !f.as_ref().unwrap().metadata().unwrap().is_dir())
             .map(|p| p.unwrap().path().to_str().unwrap().to_string())
             .map(|p| p.replace(base_folder().as_str(), ""))
-            .filter(|p| p.to_lowercase().as_str().ends_with_any(&[".jpg", ".jpeg"]))
-            .map(Image::new)
+            .filter(|p| is_source_image(p))
+            .collect();
+        let mut paths: Vec<Image> = Self::pair_raw_files(paths)
+            .into_iter()
+            .map(|image| Image {
+                modified: Self::modified(&image.path),
+                ..image
+            })
             .collect();
 
-        // TODO fix this slow copy
-        paths.sort_by_key(|k| k.path.to_string());
+        SortOrder::Name.sort(&mut paths);
         Ok(paths)
     }
 
-    fn get_image_filename(filename: &str) -> String {
+    /// List a folder in the requested order, falling back to the folder's
+    /// `.settings` default and then to natural filename order
+    pub async fn list_sorted(
+        context: &GraphQLContext,
+        folder: &str,
+        sort: Option<SortOrder>,
+    ) -> Result<Vec<Image>, ImageError> {
+        let mut images = Self::list(context, folder).await?;
+        let sort = match sort {
+            Some(sort) => sort,
+            None => FolderSettings::load(folder).await.sort.unwrap_or_default(),
+        };
+        if sort != SortOrder::Name {
+            sort.sort(&mut images);
+        }
+
+        Ok(images)
+    }
+
+    /// Up to `limit` images from `offset` of a sorted folder, or all of them
+    /// when no limit is given
+    pub async fn list_page(
+        context: &GraphQLContext,
+        folder: &str,
+        sort: Option<SortOrder>,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Result<ImagePage, ImageError> {
+        let images = Self::list_sorted(context, folder, sort).await?;
+        Ok(Self::page(context, images, offset, limit.unwrap_or(usize::MAX)).await)
+    }
+
+    /// Up to `limit` images following the one `cursor` points at, so a
+    /// scrolling client neither skips nor repeats images when the folder
+    /// changes between requests
+    pub async fn list_after(
+        context: &GraphQLContext,
+        folder: &str,
+        sort: Option<SortOrder>,
+        cursor: &str,
+        limit: usize,
+    ) -> Result<ImagePage, ImageError> {
+        let cursor = Cursor::decode(cursor)
+            .ok_or_else(|| ImageError::Invalid("unreadable cursor".to_string()))?;
+        let images = Self::list_sorted(context, folder, sort).await?;
+        let offset = cursor.resume(&images);
+        Ok(Self::page(context, images, offset, limit.clamp(1, MAX_SLICE)).await)
+    }
+
+    async fn page(
+        context: &GraphQLContext,
+        images: Vec<Image>,
+        offset: usize,
+        limit: usize,
+    ) -> ImagePage {
+        let total = images.len();
+        let end = offset.saturating_add(limit).min(total);
+        let next = (end > 0 && end < total).then(|| {
+            Cursor {
+                offset: end - 1,
+                path: images[end - 1].path.clone(),
+            }
+            .encode()
+        });
+        let (before, after) = Self::neighbours(&images, offset, end);
+        let images: Vec<Image> = images.into_iter().skip(offset).take(limit).collect();
+        // captions are checked per request too, as sidecars may have been
+        // edited since the folder was cached
+        let images = Self::attach_captions(&context.caption_cache, images).await;
+
+        ImagePage {
+            // read per request, as the worker may have rendered them since
+            // the folder was cached
+            images: Self::attach_placeholders(images).await,
+            total,
+            offset,
+            next,
+            before,
+            after,
+        }
+    }
+
+    /// The images just before `offset` and from `end`, wrapping around, when
+    /// `offset..end` leaves some of `images` out
+    fn neighbours(images: &[Image], offset: usize, end: usize) -> (Option<Image>, Option<Image>) {
+        let total = images.len();
+        if offset >= end || end - offset >= total {
+            return (None, None);
+        }
+        (
+            images.get((offset + total - 1) % total).cloned(),
+            images.get(end % total).cloned(),
+        )
+    }
+
+    /// Modification time of an original, in seconds since the epoch
+    pub fn modified(filename: &str) -> Option<u64> {
+        Self::modified_path(&Self::get_image_filename(filename))
+    }
+
+    fn modified_path(path: &str) -> Option<u64> {
+        let metadata = fs::metadata(path).ok()?;
+        let modified = metadata.modified().ok()?;
+        modified
+            .duration_since(UNIX_EPOCH)
+            .ok()
+            .map(|d| d.as_secs())
+    }
+
+    /// Location on disk of a full-resolution original the caller may download
+    pub async fn original(context: &GraphQLContext, filename: &str) -> Result<PathBuf, ImageError> {
+        Self::check_visible(context, filename).await?;
+        if !is_source_image(filename) {
+            return Err(ImageError::NotFound(filename.to_string()));
+        }
+        if !Self::downloads_allowed(filename).await {
+            info!("Original downloads are disabled for {filename}");
+            return Err(ImageError::NotAllowed);
+        }
+
+        let image_filename = PathBuf::from(Self::get_image_filename(filename));
+        if !image_filename.is_file() {
+            return Err(ImageError::NotFound(filename.to_string()));
+        }
+
+        Ok(image_filename)
+    }
+
+    /// Every visible, downloadable file of a folder (and optionally its
+    /// subfolders), named relative to `folder` for placing in an archive
+    pub async fn archive_entries(
+        context: &GraphQLContext,
+        folder: &str,
+        recursive: bool,
+    ) -> Result<Vec<ArchiveEntry>, ImageError> {
+        if !FolderSettings::load(folder).await.downloads {
+            info!("Original downloads are disabled for {folder}");
+            return Err(ImageError::NotAllowed);
+        }
+
+        let mut entries = vec![];
+        Self::collect_archive_entries(context, folder, folder, recursive, &mut entries).await?;
+        Ok(entries)
+    }
+
+    #[async_recursion]
+    async fn collect_archive_entries(
+        context: &GraphQLContext,
+        root: &str,
+        folder: &str,
+        recursive: bool,
+        entries: &mut Vec<ArchiveEntry>,
+    ) -> Result<(), ImageError> {
+        let root = strip_slashes(root);
+        for image in Self::list(context, folder).await? {
+            for file in image.files {
+                let name = strip_slashes(&file);
+                let name = name.strip_prefix(root).unwrap_or(name);
+                entries.push(ArchiveEntry {
+                    name: strip_slashes(name).to_string(),
+                    path: PathBuf::from(Self::get_image_filename(&file)),
+                });
+            }
+        }
+
+        if recursive {
+            let folders = FolderSvc::list(context, folder).await?;
+            for inner in folders {
+                if !FolderSettings::load(&inner.path).await.downloads {
+                    continue;
+                }
+                Self::collect_archive_entries(context, root, &inner.path, recursive, entries)
+                    .await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the folder containing `filename` permits original downloads
+    pub async fn downloads_allowed(filename: &str) -> bool {
+        let folder = match filename.rfind('/') {
+            Some(pos) => &filename[..pos],
+            None => "/",
+        };
+
+        FolderSettings::load(folder).await.downloads
+    }
+
+    /// Refuse paths that escape `PHOTO_DIR` or sit in a folder hidden from the caller
+    async fn check_visible(context: &GraphQLContext, filename: &str) -> Result<(), ImageError> {
+        if filename.contains("..") {
+            error!("Attempt to traverse upward: {filename}");
+            return Err(ImageError::Invalid("path may not contain '..'".to_string()));
+        }
+
+        let pos = filename.rfind('/');
+        let (folder, _) = if let Some(pos) = pos {
+            filename.split_at(pos)
+        } else {
+            ("/", filename)
+        };
+        if Self::is_hidden(folder, &context.auth).await {
+            error!(
+                "Attempt to view a hidden directory: {folder} with auth {:?}",
+                &context.auth
+            );
+            return Err(ImageError::NotAllowed);
+        }
+
+        Ok(())
+    }
+
+    /// EXIF data and display size, both read from headers rather than by
+    /// decoding the image
+    async fn attach_exif(exif_cache: &ExifCache, images: Vec<Image>) -> Vec<Image> {
+        stream::iter(images)
+            .map(|image| async move {
+                let exif = exif_cache.get(&image.path).await.ok();
+                let size = exif.as_ref().and_then(display_size);
+                Image {
+                    width: size.map(|(width, _)| width),
+                    height: size.map(|(_, height)| height),
+                    aspect_ratio: size
+                        .filter(|(_, height)| *height > 0)
+                        .map(|(width, height)| f64::from(width) / f64::from(height)),
+                    exif,
+                    ..image
+                }
+            })
+            .buffered(16)
+            .collect()
+            .await
+    }
+
+    pub(crate) async fn attach_captions(captions: &CaptionCache, images: Vec<Image>) -> Vec<Image> {
+        stream::iter(images)
+            .map(|image| async move {
+                let caption = captions.get(&image).await;
+                Image { caption, ..image }
+            })
+            .buffered(16)
+            .collect()
+            .await
+    }
+
+    async fn attach_placeholders(images: Vec<Image>) -> Vec<Image> {
+        stream::iter(images)
+            .map(|image| async move {
+                let placeholder = Self::placeholder(&image.path).await;
+                Image {
+                    placeholder,
+                    ..image
+                }
+            })
+            .buffered(16)
+            .collect()
+            .await
+    }
+
+    /// The placeholder hash stored with an image's thumbnails, if one has
+    /// been rendered for the current original
+    pub async fn placeholder(filename: &str) -> Option<String> {
+        // placeholders are named by identity alone, so skip reading the orientation
+        let original = PathBuf::from(Self::get_image_filename(filename));
+        let identity = tokio::task::spawn_blocking(move || source_identity(&original))
+            .await
+            .ok()?;
+        let placeholder_filename = Self::get_placeholder_filename(filename, identity.as_deref());
+        let hash = tokio::fs::read_to_string(placeholder_filename).await.ok()?;
+        Some(hash.trim().to_string())
+    }
+
+    pub async fn exif(context: &GraphQLContext, filename: &str) -> Result<ExifData, ImageError> {
+        Self::check_visible(context, filename).await?;
+        if !is_source_image(filename) {
+            return Err(ImageError::NotFound(filename.to_string()));
+        }
+
+        context.exif_cache.get(filename).await
+    }
+
+    /// Pair each RAW file with a rendered image sharing its stem into one
+    /// `Image`, preferring the rendered file as its `path`. Stems compare
+    /// case-insensitively; any other collision, such as `foo.jpg` and
+    /// `foo.png`, stays as separate images.
+    fn pair_raw_files(mut paths: Vec<String>) -> Vec<Image> {
+        // read_dir order is arbitrary, so sort to pair the same files every time
+        paths.sort();
+        let mut images: Vec<Image> = Vec::new();
+        let mut by_stem: HashMap<String, Vec<usize>> = HashMap::new();
+        for path in paths {
+            let stem = match path.rfind('.') {
+                Some(pos) => path[..pos].to_lowercase(),
+                None => path.to_lowercase(),
+            };
+            let candidates = by_stem.entry(stem).or_default();
+            let partner = candidates
+                .iter()
+                .copied()
+                .find(|i| images[*i].files.len() == 1 && is_raw(&images[*i].path) != is_raw(&path));
+            match partner {
+                Some(i) if is_raw(&path) => images[i].files.push(path),
+                Some(i) => {
+                    let image = &mut images[i];
+                    image.files.insert(0, path.clone());
+                    image.path = path;
+                }
+                None => {
+                    candidates.push(images.len());
+                    images.push(Image::new(path));
+                }
+            }
+        }
+
+        images
+    }
+
+    /// Embedded full-size JPEG preview of a RAW original
+    pub async fn preview(context: &GraphQLContext, filename: &str) -> Result<Vec<u8>, ImageError> {
+        Self::check_visible(context, filename).await?;
+        if !is_raw(filename) {
+            return Err(ImageError::NotFound(filename.to_string()));
+        }
+
+        let image_filename = Self::get_image_filename(filename);
+        tokio::task::spawn_blocking(move || crate::raw::read_preview(Path::new(&image_filename)))
+            .await
+            .map_err(|e| ImageError::FsError(e.to_string()))?
+            .map_err(|e| ImageError::io(filename, e))
+    }
+
+    /// Decode an original with the loader of its source format
+    fn open_source(filename: &str) -> Result<DynamicImage, ImgError> {
+        let image_filename = Self::get_image_filename(filename);
+        match source_format(&image_filename) {
+            Some(format) => format.load(Path::new(&image_filename)),
+            None => image::io::Reader::open(image_filename)?
+                .with_guessed_format()?
+                .decode(),
+        }
+    }
+
+    pub(crate) fn get_image_filename(filename: &str) -> String {
         let base_folder = get_base_folder();
         let filename = strip_slashes(filename);
         format!("{}/{}", base_folder, filename)
     }
 
+    /// The composite thumbnail cached for folders without images of their own;
+    /// WebP keeps the original extension-less `thumb-<size>` name
+    fn get_folder_thumb_filename(folder: &str, size: u32, format: ThumbFormat) -> String {
+        let thumb_folder = folder_thumb_folder(folder);
+        match format {
+            ThumbFormat::Webp => format!("{}/thumb-{}", thumb_folder, size),
+            _ => format!("{}/thumb-{}.{}", thumb_folder, size, format.extension()),
+        }
+    }
+
     #[async_recursion]
     pub async fn get_folder_thumbnail(
         context: &GraphQLContext,
         folder: &str,
         size: u32,
-    ) -> Result<Vec<u8>, ImageError> {
+        format: ThumbFormat,
+    ) -> Result<Thumbnail, ImageError> {
         let thumb_path = format!("{}/thumb", folder);
-        let full_thumb_path = format!("{}{}-{}", base_folder(), thumb_path, size);
-        let file_res = tokio::fs::File::open(full_thumb_path).await;
+        let full_thumb_path = Self::get_folder_thumb_filename(folder, size, format);
+        let file_res = tokio::fs::File::open(&full_thumb_path).await;
         if let Ok(mut file) = file_res {
             let mut contents: Vec<u8> = vec![];
             let read_res = file.read_to_end(&mut contents).await;
             if read_res.is_ok() {
-                return Ok(contents);
+                return Ok(Thumbnail {
+                    data: contents,
+                    path: PathBuf::from(full_thumb_path),
+                });
             }
         }
-        let thumb = ImageSvc::thumbnail(context, &thumb_path, size).await;
-        if let Ok(thumb) = thumb {
-            return Ok(thumb);
+        // a cover picked by hand, named `thumb` without an extension; only
+        // queued when there is one, as most folders have none
+        let cover = Self::get_image_filename(&thumb_path);
+        if tokio::fs::metadata(cover).await.is_ok_and(|m| m.is_file()) {
+            let thumb = ImageSvc::thumbnail(context, &thumb_path, size, format).await;
+            if let Ok(thumb) = thumb {
+                return Ok(thumb);
+            }
         }
 
         let files_res = Self::list_internal(folder, &context.auth).await;
         let files: Vec<Image> = files_res.unwrap_or_default();
 
         if !files.is_empty() {
-            return ImageSvc::thumbnail(context, &files[0].path, size).await;
+            return ImageSvc::thumbnail(context, &files[0].path, size, format).await;
         }
 
         let folders_res = FolderSvc::list(context, folder).await;
         let folders: Vec<Folder> = folders_res.unwrap_or_default();
 
         if folders.is_empty() {
-            return Err(ImageError::ThumbError);
+            return Err(ImageError::NotFound(format!("{folder} has no images")));
         }
 
         let inner_folder = &folders[0];
-        let image_data = Self::get_folder_thumbnail(context, &inner_folder.path, size).await?;
+        let inner = Self::get_folder_thumbnail(context, &inner_folder.path, size, format).await?;
 
         info!("Creating thumb file {}", thumb_path);
-        let thumb_filename = format!("{}{}-{}", base_folder(), thumb_path, size);
-        let mut file = tokio::fs::File::create(thumb_filename)
-            .await
-            .expect("Could not create thumb file");
-        file.write_all(&image_data)
-            .await
-            .expect("Could not write to thumb file");
-        Ok(image_data)
+        let data = inner.data.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            write_atomic(Path::new(&full_thumb_path), &data).map(|_| full_thumb_path)
+        })
+        .await;
+        match result {
+            Ok(Ok(written)) => Ok(Thumbnail {
+                data: inner.data,
+                path: PathBuf::from(written),
+            }),
+            result => {
+                error!("Could not write folder thumb {}: {:?}", thumb_path, result);
+                Ok(inner)
+            }
+        }
     }
 
     fn get_thumb_dirname(filename: &str) -> String {
-        let base_folder = get_base_folder();
         let pos = filename.rfind('/');
         let (folder, _) = if let Some(pos) = pos {
             filename.split_at(pos)
         } else {
             ("/", filename)
         };
-        let folder = strip_slashes(folder);
 
-        if folder.is_empty() {
-            format!("{}/.thumbs", base_folder)
-        } else {
-            format!("{}/{}/.thumbs", base_folder, folder)
-        }
+        thumb_folder(folder)
     }
-    fn get_thumb_filename(filename: &str, size: u32) -> String {
-        let base_folder = get_base_folder();
+    fn get_thumb_filename(filename: &str, size: u32, format: ThumbFormat) -> String {
         let pos = filename.rfind('/');
         let (folder, filename) = if let Some(pos) = pos {
             filename.split_at(pos)
         } else {
             ("/", filename)
         };
-        let folder = strip_slashes(folder);
         let filename = strip_slashes(filename);
 
-        if folder.is_empty() {
-            format!("{}/.thumbs/{}-{}.webp", base_folder, filename, size)
-        } else {
-            format!(
-                "{}/{}/.thumbs/{}-{}.webp",
-                base_folder, folder, filename, size
-            )
+        format!(
+            "{}/{}-{}.{}",
+            thumb_folder(folder),
+            filename,
+            size,
+            format.extension()
+        )
+    }
+
+    /// Thumbnails of images carrying a non-default EXIF orientation are stored
+    /// under an orientation-qualified name (`foo.jpg-300-o6.webp`), so a plain
+    /// `foo.jpg-300.webp` for such an image predates orientation handling
+    fn get_oriented_thumb_filename(
+        filename: &str,
+        size: u32,
+        orientation: u32,
+        format: ThumbFormat,
+    ) -> String {
+        let thumb_filename = Self::get_thumb_filename(filename, size, format);
+        if orientation <= 1 {
+            return thumb_filename;
         }
+
+        let extension = format.extension();
+        let stem = thumb_filename
+            .strip_suffix(&format!(".{}", extension))
+            .unwrap_or(&thumb_filename);
+        format!("{}-o{}.{}", stem, orientation, extension)
     }
 
-    pub async fn thumbnail(
-        context: &GraphQLContext,
+    /// Thumbnails also carry the identity of the original they were rendered
+    /// from (`foo.jpg-300-o6-1a2b3c4d.webp`), so re-exporting an original
+    /// renders new ones
+    fn get_keyed_thumb_filename(
         filename: &str,
         size: u32,
-    ) -> Result<Vec<u8>, ImageError> {
+        orientation: u32,
+        identity: Option<&str>,
+        format: ThumbFormat,
+    ) -> String {
+        let thumb_filename = Self::get_oriented_thumb_filename(filename, size, orientation, format);
+        let Some(identity) = identity else {
+            return thumb_filename;
+        };
+
+        let extension = format.extension();
+        let stem = thumb_filename
+            .strip_suffix(&format!(".{}", extension))
+            .unwrap_or(&thumb_filename);
+        format!("{}-{}.{}", stem, identity, extension)
+    }
+
+    /// Placeholder hashes sit beside the thumbnails, as `foo.jpg-32-1a2b3c4d.blurhash`
+    fn get_placeholder_filename(filename: &str, identity: Option<&str>) -> String {
+        let name = strip_slashes(filename.rsplit('/').next().unwrap_or(filename));
+        let identity = identity.map(|i| format!("-{i}")).unwrap_or_default();
+        format!(
+            "{}/{}-{}{}.{}",
+            Self::get_thumb_dirname(filename),
+            name,
+            PLACEHOLDER_SIZE,
+            identity,
+            PLACEHOLDER_EXTENSION
+        )
+    }
+
+    /// Render and store the placeholder hash of an oriented original, unless
+    /// it already exists. Placeholders are best effort, so failures are only logged.
+    fn write_placeholder(placeholder_filename: &str, img: &DynamicImage) {
+        single_flight(placeholder_filename, || {
+            let path = Path::new(placeholder_filename);
+            if path.exists() {
+                return;
+            }
+            let Some(hash) = placeholder::encode(img, PLACEHOLDER_SIZE) else {
+                error!("Could not compute placeholder {}", placeholder_filename);
+                return;
+            };
+            if let Err(e) = write_atomic(path, hash.as_bytes()) {
+                error!(
+                    "Could not save placeholder {}: {:?}",
+                    placeholder_filename, e
+                );
+            }
+        })
+    }
+
+    /// EXIF orientation and identity of the original, which together name its
+    /// thumbnails
+    fn source_key(filename: &str) -> (u32, Option<String>) {
+        let original = Self::get_image_filename(filename);
+        let original = Path::new(&original);
+        (read_orientation(original), source_identity(original))
+    }
+
+    fn ensure_thumb_dir(filename: &str) {
         let thumb_directory = Self::get_thumb_dirname(filename);
         let thumb_directory_path = Path::new(&thumb_directory);
         if !thumb_directory_path.exists() {
             trace!("Creating directory: {}", &thumb_directory);
-            let result = fs::create_dir(thumb_directory_path);
+            let result = fs::create_dir_all(thumb_directory_path);
             if result.is_err() {
                 error!(
                     "Could not create thumb directory: {} {:?}",
@@ -327,12 +949,15 @@ impl ImageSvc {
                 );
             }
         }
-        let thumb_filename = Self::get_thumb_filename(filename, size);
-        let file = Path::new(&thumb_filename);
-        if !file.exists() {
-            Self::generate_thumbnail(filename, size).await?;
-        }
+    }
 
+    pub async fn thumbnail(
+        context: &GraphQLContext,
+        filename: &str,
+        size: u32,
+        format: ThumbFormat,
+    ) -> Result<Thumbnail, ImageError> {
+        // refuse before rendering, so a forbidden request costs nothing
         let pos = filename.rfind('/');
         let (folder, _) = if let Some(pos) = pos {
             filename.split_at(pos)
@@ -347,79 +972,134 @@ impl ImageSvc {
             return Err(ImageError::NotAllowed);
         }
 
-        tokio::fs::read(&thumb_filename)
+        Self::ensure_thumb_dir(filename);
+        let (orientation, identity) = Self::source_key(filename);
+        let thumb_filename = Self::get_keyed_thumb_filename(
+            filename,
+            size,
+            orientation,
+            identity.as_deref(),
+            format,
+        );
+        let file = Path::new(&thumb_filename);
+        if !file.exists() {
+            Self::generate_thumbnail(filename, size, format).await?;
+        }
+
+        let data = tokio::fs::read(&thumb_filename)
             .await
-            .map_err(|_| ImageError::FsError)
+            .map_err(|e| ImageError::io(filename, e))?;
+        Ok(Thumbnail {
+            data,
+            path: PathBuf::from(thumb_filename),
+        })
     }
 
-    async fn generate_thumbnail(filename: &str, size: u32) -> Result<(), ImageError> {
-        let thumb_filename = Self::get_thumb_filename(filename, size);
-        let image_filename = Self::get_image_filename(filename);
-        let img: Result<DynamicImage, ImgError> = image::open(image_filename);
+    async fn generate_thumbnail(
+        filename: &str,
+        size: u32,
+        format: ThumbFormat,
+    ) -> Result<(), ImageError> {
+        // duplicate requests wait here rather than each taking a queue thread
+        let key = format!("{filename}-{size}.{}", format.extension());
+        let filename = filename.to_string();
+        coalesce(&key, move || Self::generate_queued(filename, size, format)).await
+    }
 
-        if img.is_err() {
-            return Ok(());
-        }
+    async fn generate_queued(
+        filename: String,
+        size: u32,
+        format: ThumbFormat,
+    ) -> Result<(), ImageError> {
+        run_job(Priority::Interactive, move || {
+            let (orientation, identity) = Self::source_key(&filename);
+            let thumb_filename = Self::get_keyed_thumb_filename(
+                &filename,
+                size,
+                orientation,
+                identity.as_deref(),
+                format,
+            );
 
-        let img = img.unwrap();
-        let result = tokio::task::spawn_blocking(move || {
-            let rgb = img.into_rgb8();
-            let data: DynamicImage =
-                DynamicImage::ImageRgb8(rgb).resize(size, size, FilterType::CatmullRom);
-            let encoder: Encoder = Encoder::from_image(&data).unwrap();
-            let webp: WebPMemory = encoder.encode(82f32);
-            let result =
-                std::fs::write(&thumb_filename, &*webp).map_err(|_| ImageError::ThumbError);
-            if result.is_err() {
-                println!("Could not create thumb: {}", &thumb_filename);
-            }
+            single_flight(&thumb_filename, || {
+                // whoever held the lock before us may have written it already
+                if Path::new(&thumb_filename).exists() {
+                    return Ok(());
+                }
+                let img =
+                    Self::open_source(&filename).map_err(|e| ImageError::decode(&filename, e))?;
+                let img = apply_orientation(img, orientation);
+                let placeholder_filename =
+                    Self::get_placeholder_filename(&filename, identity.as_deref());
+                if !Path::new(&placeholder_filename).exists() {
+                    Self::write_placeholder(&placeholder_filename, &img);
+                }
+                let data: DynamicImage = img.resize(size, size, FilterType::CatmullRom);
+                let result = format.encode(&data).and_then(|encoded| {
+                    write_atomic(Path::new(&thumb_filename), &encoded)
+                        .map_err(|e| ImageError::FsError(e.to_string()))
+                });
+                if let Err(e) = &result {
+                    error!("Could not create thumb: {} {:?}", thumb_filename, e);
+                }
+                result
+            })
         })
-        .await;
+        .await?
+    }
+}
 
-        if result.is_err() {
-            println!("Could not asynchronously run the generation");
-        }
+/// An original decoded once, so each of its missing thumbnails can be
+/// rendered as a queue job of its own
+pub struct Pregeneration {
+    filename: String,
+    orientation: u32,
+    identity: Option<String>,
+    image: Arc<RgbImage>,
+    /// Sizes and formats without a thumbnail yet
+    pub missing: Vec<(u32, ThumbFormat)>,
+}
 
-        Ok(())
-    }
-    fn generate_thumbnails(filename: &str, sizes: Vec<u32>) -> Result<(), ImageError> {
-        let image_filename = Self::get_image_filename(filename);
-        let thumb_filename = Self::get_thumb_filename(filename, 2400);
-        let file = Path::new(&thumb_filename);
-        if !file.exists() {
-            let img: Result<DynamicImage, ImgError> = image::open(image_filename);
-            let img = img.unwrap();
-            let rgb = img.into_rgb8();
-
-            sizes.par_iter().for_each(|size| {
-                let thumb_filename = Self::get_thumb_filename(filename, *size);
-                let file = Path::new(&thumb_filename);
-                if !file.exists() {
-                    let data: DynamicImage = DynamicImage::ImageRgb8(rgb.clone()).resize(
-                        *size,
-                        *size,
-                        FilterType::CatmullRom,
-                    );
-                    let encoder: Encoder = Encoder::from_image(&data).unwrap();
-                    let webp: WebPMemory = encoder.encode(82f32);
-                    let result =
-                        std::fs::write(&thumb_filename, &*webp).map_err(|_| ImageError::ThumbError);
-
-                    if result.is_err() {
-                        println!(
-                            "Could not save thumb: {} {:?}",
-                            &thumb_filename,
-                            result.err()
-                        );
-                    }
-                }
-            });
+impl Pregeneration {
+    /// Render one missing thumbnail, unless someone else got there first
+    pub fn render(&self, size: u32, format: ThumbFormat) -> Result<(), ImageError> {
+        let thumb_filename = ImageSvc::get_keyed_thumb_filename(
+            &self.filename,
+            size,
+            self.orientation,
+            self.identity.as_deref(),
+            format,
+        );
+        let result = single_flight(&thumb_filename, || {
+            if Path::new(&thumb_filename).exists() {
+                return Ok(());
+            }
+            // resized from the shared original rather than a copy of it
+            let (width, height) = fit(self.image.dimensions(), size);
+            let resized = imageops::resize(&*self.image, width, height, FilterType::CatmullRom);
+            format
+                .encode(&DynamicImage::ImageRgb8(resized))
+                .and_then(|encoded| {
+                    write_atomic(Path::new(&thumb_filename), &encoded)
+                        .map_err(|e| ImageError::FsError(e.to_string()))
+                })
+        });
+        if let Err(e) = &result {
+            error!("Could not save thumb: {} {:?}", thumb_filename, e);
         }
-
-        Ok(())
+        result
     }
 }
 
+/// Dimensions of an image scaled to fit a `size` square, keeping its aspect
+/// ratio as `DynamicImage::resize` does
+fn fit((width, height): (u32, u32), size: u32) -> (u32, u32) {
+    let scale =
+        (f64::from(size) / f64::from(width.max(1))).min(f64::from(size) / f64::from(height.max(1)));
+    let scaled = |side: u32| ((f64::from(side) * scale).round() as u32).max(1);
+    (scaled(width), scaled(height))
+}
+
 #[cfg(test)]
 pub mod tests {
     use super::*;
@@ -496,7 +1176,9 @@ pub mod tests {
     #[tokio::test]
     pub async fn it_generates_a_thumbnail() {
         dotenvy::from_filename(".env.test").ok();
-        let result = ImageSvc::generate_thumbnail("/SidawayFamilyShoot11574.jpg", 222).await;
+        let result =
+            ImageSvc::generate_thumbnail("/SidawayFamilyShoot11574.jpg", 222, ThumbFormat::Webp)
+                .await;
         assert!(result.is_ok());
     }
     #[tokio::test]
@@ -504,26 +1186,116 @@ pub mod tests {
         dotenvy::from_filename(".env.test").ok();
         let path = "/SidawayFamilyShoot11574.jpg";
         let size = 222;
-        let result = ImageSvc::generate_thumbnail(path, size).await;
+        let result = ImageSvc::generate_thumbnail(path, size, ThumbFormat::Webp).await;
         assert!(result.is_ok());
-        let thumb = ImageSvc::thumbnail(&GraphQLContext::default(), path, size).await;
+        let thumb =
+            ImageSvc::thumbnail(&GraphQLContext::default(), path, size, ThumbFormat::Webp).await;
         assert!(thumb.is_ok());
         let thumb = thumb.unwrap();
-        assert!(thumb.len() > 100);
+        assert!(thumb.data.len() > 100);
     }
     #[test]
     pub fn it_gets_thumb_filename_for_root() {
         dotenvy::from_filename(".env.test").ok();
-        let filename = ImageSvc::get_thumb_filename("/test.jpg", 222);
+        let filename = ImageSvc::get_thumb_filename("/test.jpg", 222, ThumbFormat::Webp);
         assert_eq!(filename, "./photos/.thumbs/test.jpg-222.webp");
     }
     #[test]
     pub fn it_gets_thumb_filename_for_nested() {
         dotenvy::from_filename(".env.test").ok();
-        let filename = ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 222);
+        let filename = ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 222, ThumbFormat::Webp);
         assert_eq!(filename, "./photos/Pets/.thumbs/D75_0360.jpg-222.webp");
     }
     #[test]
+    pub fn it_gets_thumb_filename_per_format() {
+        dotenvy::from_filename(".env.test").ok();
+        let avif = ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 222, ThumbFormat::Avif);
+        let jpeg = ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 222, ThumbFormat::Jpeg);
+        assert!(avif.ends_with("/Pets/.thumbs/D75_0360.jpg-222.avif"));
+        assert!(jpeg.ends_with("/Pets/.thumbs/D75_0360.jpg-222.jpg"));
+    }
+    #[test]
+    pub fn it_gets_oriented_thumb_filename() {
+        dotenvy::from_filename(".env.test").ok();
+        let upright =
+            ImageSvc::get_oriented_thumb_filename("/Pets/D75_0360.jpg", 222, 1, ThumbFormat::Webp);
+        let rotated =
+            ImageSvc::get_oriented_thumb_filename("/Pets/D75_0360.jpg", 222, 6, ThumbFormat::Webp);
+        assert_eq!(
+            upright,
+            ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 222, ThumbFormat::Webp)
+        );
+        assert_eq!(rotated, upright.replace(".webp", "-o6.webp"));
+    }
+    #[test]
+    pub fn it_gets_keyed_thumb_filename() {
+        dotenvy::from_filename(".env.test").ok();
+        let rotated =
+            ImageSvc::get_oriented_thumb_filename("/Pets/D75_0360.jpg", 222, 6, ThumbFormat::Avif);
+        let keyed = ImageSvc::get_keyed_thumb_filename(
+            "/Pets/D75_0360.jpg",
+            222,
+            6,
+            Some("1a2b3c4d"),
+            ThumbFormat::Avif,
+        );
+        assert_eq!(keyed, rotated.replace(".avif", "-1a2b3c4d.avif"));
+    }
+    #[test]
+    pub fn it_names_placeholders_like_thumbnails() {
+        dotenvy::from_filename(".env.test").ok();
+        let placeholder =
+            ImageSvc::get_placeholder_filename("/Pets/D75_0360.jpg", Some("1a2b3c4d"));
+        let thumb = ImageSvc::get_thumb_filename("/Pets/D75_0360.jpg", 32, ThumbFormat::Webp);
+        assert_eq!(placeholder, thumb.replace(".webp", "-1a2b3c4d.blurhash"));
+
+        let name = placeholder.rsplit('/').next().unwrap();
+        let parsed = crate::thumbnail::parse_thumb_name(name).unwrap();
+        assert_eq!(parsed.source, "D75_0360.jpg");
+        assert_eq!(parsed.identity, Some("1a2b3c4d"));
+    }
+    #[test]
+    pub fn it_pairs_raw_and_jpeg_files() {
+        let mut images = ImageSvc::pair_raw_files(vec![
+            "/Shoot/DSC_0001.NEF".to_string(),
+            "/Shoot/DSC_0001.jpg".to_string(),
+            "/Shoot/DSC_0002.NEF".to_string(),
+        ]);
+        images.sort_by_key(|i| i.path.to_string());
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0].path, "/Shoot/DSC_0001.jpg");
+        assert_eq!(
+            images[0].files,
+            vec!["/Shoot/DSC_0001.jpg", "/Shoot/DSC_0001.NEF"]
+        );
+        assert_eq!(images[1].path, "/Shoot/DSC_0002.NEF");
+    }
+
+    #[test]
+    pub fn it_only_pairs_raw_with_rendered_files() {
+        let mut images = ImageSvc::pair_raw_files(vec![
+            "/Shoot/IMG_1.png".to_string(),
+            "/Shoot/IMG_1.NEF".to_string(),
+            "/Shoot/img_1.jpg".to_string(),
+            "/Shoot/scan.jpg".to_string(),
+            "/Shoot/scan.tif".to_string(),
+        ]);
+        images.sort_by_key(|i| i.path.to_string());
+        let files: Vec<Vec<&str>> = images
+            .iter()
+            .map(|i| i.files.iter().map(String::as_str).collect())
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                vec!["/Shoot/IMG_1.png", "/Shoot/IMG_1.NEF"],
+                vec!["/Shoot/img_1.jpg"],
+                vec!["/Shoot/scan.jpg"],
+                vec!["/Shoot/scan.tif"],
+            ]
+        );
+    }
+    #[test]
     pub fn it_strips_slashes() {
         let result = strip_slashes("/asdf.jpg");
         assert_eq!(result, "asdf.jpg");
@@ -538,4 +1310,77 @@ pub mod tests {
         let result = strip_slashes("asdf.jpg/");
         assert_eq!(result, "asdf.jpg");
     }
+    #[test]
+    pub fn it_round_trips_cursors() {
+        let cursor = Cursor {
+            offset: 24,
+            path: "/Scans/1999: summer/0025.tif".to_string(),
+        };
+        assert_eq!(Cursor::decode(&cursor.encode()), Some(cursor));
+        assert_eq!(Cursor::decode("not a cursor!"), None);
+    }
+    #[test]
+    pub fn it_resumes_after_the_cursor() {
+        let images: Vec<Image> = ["/a.jpg", "/b.jpg", "/c.jpg", "/d.jpg"]
+            .iter()
+            .map(|path| Image::new(path.to_string()))
+            .collect();
+        let after_b = Cursor {
+            offset: 1,
+            path: "/b.jpg".to_string(),
+        };
+        assert_eq!(after_b.resume(&images), 2);
+
+        // b was deleted, so c moved up to where b was
+        let without_b: Vec<Image> = images
+            .into_iter()
+            .filter(|image| image.path != "/b.jpg")
+            .collect();
+        assert_eq!(after_b.resume(&without_b), 1);
+    }
+    #[test]
+    pub fn it_reports_missing_originals_as_not_found() {
+        let missing = ImgError::IoError(io::Error::from(io::ErrorKind::NotFound));
+        assert_eq!(
+            ImageError::decode("/a.jpg", missing).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        let corrupt = ImgError::Unsupported(image::error::UnsupportedError::from(
+            image::error::ImageFormatHint::Unknown,
+        ));
+        assert_eq!(
+            ImageError::decode("/a.jpg", corrupt).status_code(),
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+    #[test]
+    pub fn it_fits_thumbnails_in_a_square() {
+        assert_eq!(fit((4000, 3000), 300), (300, 225));
+        assert_eq!(fit((3000, 4000), 300), (225, 300));
+        assert_eq!(fit((100, 50), 300), (300, 150));
+        assert_eq!(fit((10000, 1), 300), (300, 1));
+    }
+    #[test]
+    pub fn it_finds_the_neighbours_of_a_page() {
+        let images: Vec<Image> = ["/a.jpg", "/b.jpg", "/c.jpg", "/d.jpg"]
+            .iter()
+            .map(|path| Image::new(path.to_string()))
+            .collect();
+        let paths = |(before, after): (Option<Image>, Option<Image>)| {
+            (before.map(|i| i.path), after.map(|i| i.path))
+        };
+        assert_eq!(
+            paths(ImageSvc::neighbours(&images, 1, 3)),
+            (Some("/a.jpg".to_string()), Some("/d.jpg".to_string()))
+        );
+        assert_eq!(
+            paths(ImageSvc::neighbours(&images, 0, 2)),
+            (Some("/d.jpg".to_string()), Some("/c.jpg".to_string()))
+        );
+        assert_eq!(
+            paths(ImageSvc::neighbours(&images, 2, 4)),
+            (Some("/b.jpg".to_string()), Some("/a.jpg".to_string()))
+        );
+        assert_eq!(paths(ImageSvc::neighbours(&images, 0, 4)), (None, None));
+    }
 }
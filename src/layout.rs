@@ -0,0 +1,150 @@
+/// Assumed for images whose dimensions could not be read
+const FALLBACK_ASPECT_RATIO: f64 = 1.0;
+
+/// Where one image sits in a justified gallery, in CSS pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub width: f64,
+    pub height: f64,
+    /// Thumbnail sizes to request for 1x and 2x screens
+    pub size: u32,
+    pub size_2x: u32,
+    /// Whether the tile grows and shrinks with its row to keep filling the
+    /// gallery; the last row keeps its size
+    pub stretch: bool,
+}
+
+impl Tile {
+    /// CSS `flex` of the tile's figure: stretched tiles share their row in
+    /// proportion to their aspect ratios, which keeps a row justified at any
+    /// width until it is laid out again
+    pub fn flex(&self) -> String {
+        if self.stretch {
+            format!("flex: {} 1 0%", self.width / self.height)
+        } else {
+            format!("flex: none; width: {}px", self.width)
+        }
+    }
+}
+
+/// The smallest allowed thumbnail covering `needed` pixels, or the largest
+/// when none does
+pub fn pick_size(sizes: &[u32], needed: f64) -> Option<u32> {
+    sizes
+        .iter()
+        .copied()
+        .filter(|size| f64::from(*size) >= needed)
+        .min()
+        .or_else(|| sizes.iter().copied().max())
+}
+
+fn tile(aspect_ratio: f64, height: f64, stretch: bool, sizes: &[u32], fallback_size: u32) -> Tile {
+    let width = aspect_ratio * height;
+    let longest = width.max(height);
+    Tile {
+        width,
+        height,
+        size: pick_size(sizes, longest).unwrap_or(fallback_size),
+        size_2x: pick_size(sizes, longest * 2.0).unwrap_or(fallback_size * 2),
+        stretch,
+    }
+}
+
+/// Break images into rows that exactly fill `width`, each as close to
+/// `target_height` as their aspect ratios allow. The last row keeps
+/// `target_height` rather than being stretched to fill.
+pub fn justify(
+    aspect_ratios: &[Option<f64>],
+    width: f64,
+    target_height: f64,
+    gap: f64,
+    sizes: &[u32],
+    fallback_size: u32,
+) -> Vec<Vec<Tile>> {
+    let ratios: Vec<f64> = aspect_ratios
+        .iter()
+        .map(|ratio| {
+            ratio
+                .filter(|r| r.is_finite() && *r > 0.0)
+                .unwrap_or(FALLBACK_ASPECT_RATIO)
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut ratio_sum = 0.0;
+    for (end, ratio) in ratios.iter().enumerate() {
+        ratio_sum += ratio;
+        let gaps = gap * (end - start) as f64;
+        let height = (width - gaps).max(1.0) / ratio_sum;
+        if height <= target_height {
+            rows.push(
+                ratios[start..=end]
+                    .iter()
+                    .map(|ratio| tile(*ratio, height, true, sizes, fallback_size))
+                    .collect(),
+            );
+            start = end + 1;
+            ratio_sum = 0.0;
+        }
+    }
+
+    if start < ratios.len() {
+        rows.push(
+            ratios[start..]
+                .iter()
+                .map(|ratio| tile(*ratio, target_height, false, sizes, fallback_size))
+                .collect(),
+        );
+    }
+    rows
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    const SIZES: [u32; 4] = [150, 300, 600, 1200];
+
+    #[test]
+    pub fn it_picks_the_smallest_covering_size() {
+        assert_eq!(pick_size(&SIZES, 200.0), Some(300));
+        assert_eq!(pick_size(&SIZES, 300.0), Some(300));
+        assert_eq!(pick_size(&SIZES, 5000.0), Some(1200));
+        assert_eq!(pick_size(&[], 200.0), None);
+    }
+
+    #[test]
+    pub fn it_fills_rows_to_the_container_width() {
+        let ratios = [Some(1.5), Some(0.75), Some(1.5), Some(1.0), Some(1.5)];
+        let rows = justify(&ratios, 1000.0, 250.0, 10.0, &SIZES, 300);
+        assert_eq!(rows.iter().map(Vec::len).sum::<usize>(), ratios.len());
+
+        let (full, last) = rows.split_at(rows.len() - 1);
+        for row in full {
+            let used: f64 =
+                row.iter().map(|t| t.width).sum::<f64>() + 10.0 * (row.len() - 1) as f64;
+            assert!((used - 1000.0).abs() < 0.01, "row is {used} wide");
+            assert!(row[0].height <= 250.0);
+            assert!(row.iter().all(|t| t.stretch));
+        }
+        assert!(last[0].iter().all(|t| t.height == 250.0 && !t.stretch));
+    }
+
+    #[test]
+    pub fn it_keeps_aspect_ratios_and_sizes_thumbnails() {
+        let rows = justify(&[Some(2.0), None], 900.0, 400.0, 0.0, &SIZES, 300);
+        let tiles = &rows[0];
+        assert_eq!(tiles.len(), 2);
+        assert!((tiles[0].width / tiles[0].height - 2.0).abs() < 1e-9);
+        assert!((tiles[1].width - tiles[1].height).abs() < 1e-9);
+        assert_eq!((tiles[0].size, tiles[0].size_2x), (600, 1200));
+    }
+
+    #[test]
+    pub fn it_flexes_stretched_tiles_by_aspect_ratio() {
+        let rows = justify(&[Some(2.0), Some(1.0)], 300.0, 200.0, 0.0, &SIZES, 300);
+        assert_eq!(rows[0][0].flex(), "flex: 2 1 0%");
+        assert_eq!(rows[1][0].flex(), "flex: none; width: 200px");
+    }
+}
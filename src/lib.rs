@@ -3,6 +3,8 @@ pub mod app;
 pub mod error_template;
 #[cfg(feature = "ssr")]
 pub mod fileserv;
+pub mod layout;
+pub mod placeholder;
 
 use std::iter::Extend;
 
@@ -30,17 +32,39 @@ use std::str::FromStr;
 #[cfg(feature = "ssr")]
 pub mod api;
 #[cfg(feature = "ssr")]
+pub mod archive;
+#[cfg(feature = "ssr")]
+pub mod caption;
+#[cfg(feature = "ssr")]
 pub mod context;
 #[cfg(feature = "ssr")]
+pub mod exif;
+#[cfg(feature = "ssr")]
 pub mod folder;
 // #[cfg(feature = "ssr")]
 // pub mod graphql;
 #[cfg(feature = "ssr")]
+pub mod format;
+#[cfg(feature = "ssr")]
 pub mod hash;
 #[cfg(feature = "ssr")]
 pub mod image;
 #[cfg(feature = "ssr")]
 pub mod pgp;
+#[cfg(feature = "ssr")]
+pub mod queue;
+#[cfg(feature = "ssr")]
+pub mod raw;
+#[cfg(feature = "ssr")]
+pub mod search;
+#[cfg(feature = "ssr")]
+pub mod settings;
+#[cfg(feature = "ssr")]
+pub mod sort;
+#[cfg(feature = "ssr")]
+pub mod thumbnail;
+#[cfg(feature = "ssr")]
+pub mod worker;
 
 thread_local! {
     static PATH_REGEX: Regex = Regex::new(r":(\d*):.*").expect("Could not compile regex");
@@ -55,6 +79,72 @@ pub struct Folder {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Image {
     pub path: String,
+    /// Every file on disk that makes up this image (e.g. a RAW and its JPEG), `path` first
+    pub files: Vec<String>,
+    pub exif: Option<ExifData>,
+    /// Modification time of `path` in seconds since the epoch
+    pub modified: Option<u64>,
+    /// BlurHash to show while the thumbnail loads
+    pub placeholder: Option<String>,
+    /// Pixel size as displayed, after EXIF orientation
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// `width / height`, so thumbnails can reserve their space before loading
+    pub aspect_ratio: Option<f64>,
+    /// From a `.txt` sidecar, or the IPTC/XMP caption embedded in the JPEG
+    pub caption: Option<String>,
+}
+
+/// A slice of a folder's images, with where it sits in the whole listing
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ImagePage {
+    pub images: Vec<Image>,
+    /// Images in the whole folder
+    pub total: usize,
+    /// Position of the first image of this page in the folder
+    pub offset: usize,
+    /// Continuation token for `get_images_after`; `None` on the last page
+    pub next: Option<String>,
+    /// The images either side of this page, wrapping around the folder, so
+    /// the lightbox can step across its edges; `None` when the page holds
+    /// the whole folder
+    pub before: Option<Image>,
+    pub after: Option<Image>,
+}
+
+/// A folder or image matching a library search
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SearchHit {
+    pub path: String,
+    /// The folder to open to see the hit; the hit itself for folders
+    pub folder: String,
+    pub is_folder: bool,
+    /// The folder's `index.txt` or the image's caption, when it has one
+    pub text: Option<String>,
+    /// Where an image sits in its folder's default order
+    pub position: Option<usize>,
+}
+
+/// Capture metadata read from an original's EXIF block
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ExifData {
+    /// Local capture time as `YYYY-MM-DDTHH:MM:SS`
+    pub captured_at: Option<String>,
+    pub camera: Option<String>,
+    pub lens: Option<String>,
+    /// Focal length in millimetres
+    pub focal_length: Option<f64>,
+    /// Aperture as an f-number
+    pub aperture: Option<f64>,
+    /// Exposure time, e.g. `1/250` or `2s`
+    pub shutter: Option<String>,
+    pub iso: Option<u32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    /// EXIF orientation, 1 to 8
+    pub orientation: Option<u32>,
 }
 
 /// Return an environment variable typed generically
@@ -10,14 +10,38 @@ async fn main() {
     use photo_365::app::*;
     use photo_365::context::GraphQLContext;
     use photo_365::fileserv::file_and_error_handler;
+    use photo_365::get_env_typed;
     use std::sync::Arc;
 
     dotenvy::dotenv().ok();
 
+    if std::env::args().nth(1).as_deref() == Some("migrate-thumbs") {
+        tracing_subscriber::fmt::init();
+        match photo_365::thumbnail::migrate_to_thumb_dir() {
+            Ok(migration) => logging::log!("Migrated thumbnails: {:?}", migration),
+            Err(e) => {
+                logging::error!("Could not migrate thumbnails: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+    if std::env::args().nth(1).as_deref() == Some("prune-thumbs") {
+        tracing_subscriber::fmt::init();
+        let pruned = photo_365::thumbnail::prune_thumbnails();
+        logging::log!("Pruned thumbnails: {:?}", pruned);
+        return;
+    }
+
     let context = Arc::new(GraphQLContext::default());
 
     tracing_subscriber::fmt::init();
 
+    if get_env_typed("THUMB_PREGENERATE", true) {
+        context.thumbnail_worker.start(context.clone());
+    }
+    context.search_index.start();
+
     // Setting get_configuration(None) means we'll be using cargo-leptos's env values
     // For deployment these variables are:
     // <https://github.com/leptos-rs/start-axum#executing-a-server-on-a-remote-machine-without-the-toolchain>
@@ -0,0 +1,98 @@
+use base64::{engine::general_purpose, Engine as _};
+
+/// Width and height of the bitmap a placeholder is decoded to. A hash holds at
+/// most four components along a side, so a few pixels capture it, and the
+/// browser's smoothing as it stretches them over the thumbnail's box does the rest.
+const DECODED_SIZE: u32 = 8;
+
+// BITMAPFILEHEADER + BITMAPINFOHEADER
+const BMP_HEADER_LEN: u32 = 14 + 40;
+
+/// A `data:` URL of the image `hash` describes, for use as a background while
+/// the real thumbnail loads
+pub fn data_url(hash: &str) -> Option<String> {
+    let rgba = blurhash::decode(hash, DECODED_SIZE, DECODED_SIZE, 1.0).ok()?;
+    let bmp = bmp(DECODED_SIZE, DECODED_SIZE, &rgba);
+    Some(format!(
+        "data:image/bmp;base64,{}",
+        general_purpose::STANDARD.encode(bmp)
+    ))
+}
+
+/// An uncompressed 32-bit BMP, which every browser can show and takes no
+/// encoder to write. Rows are stored bottom-up as BGRA.
+fn bmp(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    let pixels_len = width * height * 4;
+    let mut bmp = Vec::with_capacity((BMP_HEADER_LEN + pixels_len) as usize);
+    bmp.extend_from_slice(b"BM");
+    bmp.extend_from_slice(&(BMP_HEADER_LEN + pixels_len).to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&BMP_HEADER_LEN.to_le_bytes());
+
+    bmp.extend_from_slice(&40u32.to_le_bytes());
+    bmp.extend_from_slice(&(width as i32).to_le_bytes());
+    bmp.extend_from_slice(&(height as i32).to_le_bytes());
+    bmp.extend_from_slice(&1u16.to_le_bytes());
+    bmp.extend_from_slice(&32u16.to_le_bytes());
+    bmp.extend_from_slice(&0u32.to_le_bytes());
+    bmp.extend_from_slice(&pixels_len.to_le_bytes());
+    bmp.extend_from_slice(&[0; 16]);
+
+    for row in rgba.chunks_exact(width as usize * 4).rev() {
+        for pixel in row.chunks_exact(4) {
+            bmp.extend_from_slice(&[pixel[2], pixel[1], pixel[0], pixel[3]]);
+        }
+    }
+    bmp
+}
+
+/// BlurHash of `img`, rendered from a copy no larger than `size`
+#[cfg(feature = "ssr")]
+pub fn encode(img: &image::DynamicImage, size: u32) -> Option<String> {
+    let small = img.thumbnail(size, size).into_rgba8();
+    // more components along the longer side keeps the shape of the blur
+    let (x, y) = if small.width() >= small.height() {
+        (4, 3)
+    } else {
+        (3, 4)
+    };
+    blurhash::encode(x, y, small.width(), small.height(), small.as_raw()).ok()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_writes_a_bottom_up_bmp() {
+        let red_over_blue = [255, 0, 0, 255, 0, 0, 255, 255];
+        let bmp = bmp(1, 2, &red_over_blue);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(bmp.len(), BMP_HEADER_LEN as usize + 8);
+        assert_eq!(
+            &bmp[BMP_HEADER_LEN as usize..],
+            &[255, 0, 0, 255, 0, 0, 255, 255]
+        );
+    }
+
+    #[test]
+    pub fn it_decodes_to_a_data_url() {
+        let url = data_url("LEHV6nWB2yk8pyo0adR*.7kCMdnj").unwrap();
+        assert!(url.starts_with("data:image/bmp;base64,Qk"));
+        assert!(url.len() < 500);
+        assert_eq!(data_url("not a hash"), None);
+    }
+
+    #[cfg(feature = "ssr")]
+    #[test]
+    pub fn it_round_trips_an_image() {
+        let img = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(
+            40,
+            20,
+            image::Rgb([200, 30, 30]),
+        ));
+        let hash = encode(&img, 32).unwrap();
+        let rgba = blurhash::decode(&hash, 1, 1, 1.0).unwrap();
+        assert!(rgba[0] > 150 && rgba[1] < 80 && rgba[2] < 80);
+    }
+}
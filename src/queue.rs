@@ -0,0 +1,254 @@
+use crate::get_env_typed;
+use crate::image::ImageError;
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+lazy_static! {
+    static ref THUMB_QUEUE: Arc<ThumbQueue> = ThumbQueue::new(
+        thumb_workers(),
+        get_env_typed("THUMB_QUEUE_DEPTH", 256usize).max(1)
+    );
+}
+
+/// How long background work waits before retrying a full queue
+const BUSY_RETRY: Duration = Duration::from_secs(1);
+
+/// Threads dedicated to thumbnail work, from `THUMB_WORKERS`
+pub fn thumb_workers() -> usize {
+    get_env_typed(
+        "THUMB_WORKERS",
+        std::thread::available_parallelism().map_or(2, |n| n.get()),
+    )
+    .max(1)
+}
+
+/// Threads background jobs may occupy at once: all but one, so a visitor's
+/// thumbnail never waits for a background job to finish
+pub fn background_workers() -> usize {
+    background_limit(thumb_workers())
+}
+
+fn background_limit(workers: usize) -> usize {
+    workers.saturating_sub(1).max(1)
+}
+
+/// Requests a visitor is waiting on run before anything queued in the background
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    Interactive,
+    Background,
+}
+
+type Job = Box<dyn FnOnce() + Send>;
+
+#[derive(Default)]
+struct Jobs {
+    interactive: VecDeque<Job>,
+    background: VecDeque<Job>,
+    /// Background jobs being worked on right now
+    background_running: usize,
+}
+
+impl Jobs {
+    fn len(&self) -> usize {
+        self.interactive.len() + self.background.len()
+    }
+
+    /// The next job to run, taking a background job only while fewer than
+    /// `background_limit` are running
+    fn pop(&mut self, background_limit: usize) -> Option<(Job, Priority)> {
+        if let Some(job) = self.interactive.pop_front() {
+            return Some((job, Priority::Interactive));
+        }
+        if self.background_running >= background_limit {
+            return None;
+        }
+        let job = self.background.pop_front()?;
+        self.background_running += 1;
+        Some((job, Priority::Background))
+    }
+}
+
+/// A fixed pool of threads working through a bounded queue of thumbnail jobs,
+/// so thumbnail generation can't take over the runtime's blocking pool
+pub struct ThumbQueue {
+    jobs: Mutex<Jobs>,
+    available: Condvar,
+    depth: usize,
+    background_limit: usize,
+}
+
+impl ThumbQueue {
+    pub fn new(workers: usize, depth: usize) -> Arc<Self> {
+        let queue = Arc::new(Self {
+            jobs: Mutex::new(Jobs::default()),
+            available: Condvar::new(),
+            depth,
+            background_limit: background_limit(workers),
+        });
+        for n in 0..workers {
+            let queue = queue.clone();
+            std::thread::Builder::new()
+                .name(format!("thumb-{n}"))
+                .spawn(move || queue.work())
+                .expect("Could not start thumbnail worker thread");
+        }
+
+        queue
+    }
+
+    fn work(&self) {
+        loop {
+            let (job, priority) = {
+                let mut jobs = self.jobs.lock().unwrap();
+                loop {
+                    if let Some(next) = jobs.pop(self.background_limit) {
+                        break next;
+                    }
+                    jobs = self.available.wait(jobs).unwrap();
+                }
+            };
+            job();
+            if priority == Priority::Background {
+                self.jobs.lock().unwrap().background_running -= 1;
+                // a background job may have been waiting for this slot
+                self.available.notify_all();
+            }
+        }
+    }
+
+    /// Queue `f` and wait for its result; `ImageError::Busy` when the queue
+    /// already holds `depth` jobs
+    pub async fn run<T, F>(&self, priority: Priority, f: F) -> Result<T, ImageError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let (tx, rx) = tokio::sync::oneshot::channel();
+        let job: Job = Box::new(move || {
+            // the caller may have given up waiting; nothing to do then
+            let _ = tx.send(f());
+        });
+
+        {
+            let mut jobs = self.jobs.lock().unwrap();
+            if jobs.len() >= self.depth {
+                warn!("Thumbnail queue is full, rejecting {:?} job", priority);
+                return Err(ImageError::Busy);
+            }
+            match priority {
+                Priority::Interactive => jobs.interactive.push_back(job),
+                Priority::Background => jobs.background.push_back(job),
+            }
+        }
+        self.available.notify_one();
+
+        rx.await
+            .map_err(|_| ImageError::ThumbError("thumbnail job did not finish".to_string()))
+    }
+}
+
+/// Run `f` on the shared thumbnail queue
+pub async fn run_job<T, F>(priority: Priority, f: F) -> Result<T, ImageError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Send + 'static,
+{
+    THUMB_QUEUE.run(priority, f).await
+}
+
+/// Run `f` on the shared thumbnail queue in the background, waiting for room
+/// whenever the queue is full rather than giving up
+pub async fn run_background<T, F>(f: F) -> Result<T, ImageError>
+where
+    T: Send + 'static,
+    F: FnOnce() -> T + Clone + Send + 'static,
+{
+    loop {
+        match run_job(Priority::Background, f.clone()).await {
+            Err(ImageError::Busy) => tokio::time::sleep(BUSY_RETRY).await,
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[tokio::test]
+    pub async fn it_runs_jobs() {
+        let queue = ThumbQueue::new(2, 4);
+        assert_eq!(queue.run(Priority::Background, || 2 + 2).await.unwrap(), 4);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    pub async fn it_rejects_jobs_when_full() {
+        let queue = ThumbQueue::new(1, 1);
+        let (block_tx, block_rx) = mpsc::channel::<()>();
+        let (started_tx, started_rx) = mpsc::channel::<()>();
+
+        // occupy the only worker, then fill the single queue slot
+        let busy = queue.clone();
+        let running = tokio::spawn(async move {
+            busy.run(Priority::Background, move || {
+                started_tx.send(()).unwrap();
+                block_rx.recv().unwrap();
+            })
+            .await
+        });
+        started_rx.recv().unwrap();
+        let queued = queue.clone();
+        let waiting = tokio::spawn(async move { queued.run(Priority::Background, || ()).await });
+        while queue.jobs.lock().unwrap().len() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let rejected = queue.run(Priority::Interactive, || ()).await;
+        assert!(matches!(rejected, Err(ImageError::Busy)));
+
+        block_tx.send(()).unwrap();
+        assert!(running.await.unwrap().is_ok());
+        assert!(waiting.await.unwrap().is_ok());
+    }
+
+    #[test]
+    pub fn it_prefers_interactive_jobs() {
+        let mut jobs = Jobs::default();
+        let (tx, rx) = mpsc::channel();
+        let background = tx.clone();
+        jobs.background
+            .push_back(Box::new(move || background.send("background").unwrap()));
+        jobs.interactive
+            .push_back(Box::new(move || tx.send("interactive").unwrap()));
+
+        while let Some((job, _)) = jobs.pop(1) {
+            job();
+        }
+        assert_eq!(rx.recv().unwrap(), "interactive");
+        assert_eq!(rx.recv().unwrap(), "background");
+    }
+
+    #[test]
+    pub fn it_keeps_a_worker_free_of_background_jobs() {
+        assert_eq!(background_limit(4), 3);
+        assert_eq!(background_limit(1), 1);
+
+        let mut jobs = Jobs::default();
+        for _ in 0..3 {
+            jobs.background.push_back(Box::new(|| ()));
+        }
+        assert!(jobs.pop(2).is_some());
+        assert!(jobs.pop(2).is_some());
+        assert!(jobs.pop(2).is_none());
+
+        jobs.interactive.push_back(Box::new(|| ()));
+        assert!(matches!(jobs.pop(2), Some((_, Priority::Interactive))));
+        jobs.background_running -= 1;
+        assert!(matches!(jobs.pop(2), Some((_, Priority::Background))));
+    }
+}
@@ -0,0 +1,283 @@
+use image::{DynamicImage, ImageError as ImgError, ImageFormat};
+use log::*;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+
+// Guards against looping or absurdly deep IFD chains in corrupt files
+const MAX_IFDS: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Preview {
+    offset: u64,
+    length: u64,
+}
+
+struct TiffReader<R> {
+    inner: R,
+    little_endian: bool,
+}
+
+impl<R: Read + Seek> TiffReader<R> {
+    fn new(mut inner: R) -> io::Result<(Self, u32)> {
+        let mut header = [0u8; 8];
+        inner.seek(SeekFrom::Start(0))?;
+        inner.read_exact(&mut header)?;
+        let little_endian = match &header[0..2] {
+            b"II" => true,
+            b"MM" => false,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "not a TIFF")),
+        };
+        let reader = Self {
+            inner,
+            little_endian,
+        };
+        let first_ifd = reader.u32_from(&header[4..8]);
+
+        Ok((reader, first_ifd))
+    }
+
+    fn u16_from(&self, bytes: &[u8]) -> u16 {
+        let bytes = [bytes[0], bytes[1]];
+        if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        }
+    }
+
+    fn u32_from(&self, bytes: &[u8]) -> u32 {
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        }
+    }
+
+    fn read_u16(&mut self) -> io::Result<u16> {
+        let mut buf = [0u8; 2];
+        self.inner.read_exact(&mut buf)?;
+        Ok(self.u16_from(&buf))
+    }
+
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0u8; 4];
+        self.inner.read_exact(&mut buf)?;
+        Ok(self.u32_from(&buf))
+    }
+
+    /// Value of a SHORT or LONG entry stored inline in the 4 byte value field
+    fn inline_value(&self, field_type: u16, value: &[u8]) -> u32 {
+        match field_type {
+            3 => self.u16_from(value) as u32,
+            _ => self.u32_from(value),
+        }
+    }
+
+    /// Walk every IFD reachable from the header, collecting embedded JPEG streams
+    fn previews(&mut self, first_ifd: u32) -> io::Result<Vec<Preview>> {
+        let mut previews = vec![];
+        let mut pending = vec![first_ifd];
+        let mut visited = HashSet::new();
+
+        while let Some(ifd) = pending.pop() {
+            if ifd == 0 || visited.len() >= MAX_IFDS || !visited.insert(ifd) {
+                continue;
+            }
+
+            self.inner.seek(SeekFrom::Start(ifd as u64))?;
+            let count = self.read_u16()?;
+
+            let mut compression = None;
+            let mut jpeg_offset = None;
+            let mut jpeg_length = None;
+            let mut strip_offset = None;
+            let mut strip_length = None;
+            let mut sub_ifds = vec![];
+
+            let mut entries = vec![0u8; count as usize * 12];
+            self.inner.read_exact(&mut entries)?;
+            let next_ifd = self.read_u32()?;
+
+            for entry in entries.chunks_exact(12) {
+                let tag = self.u16_from(&entry[0..2]);
+                let field_type = self.u16_from(&entry[2..4]);
+                let value_count = self.u32_from(&entry[4..8]);
+                let value = self.inline_value(field_type, &entry[8..12]);
+
+                match tag {
+                    TAG_COMPRESSION => compression = Some(value),
+                    TAG_JPEG_OFFSET => jpeg_offset = Some(value),
+                    TAG_JPEG_LENGTH => jpeg_length = Some(value),
+                    TAG_STRIP_OFFSETS if value_count == 1 => strip_offset = Some(value),
+                    TAG_STRIP_BYTE_COUNTS if value_count == 1 => strip_length = Some(value),
+                    TAG_SUB_IFDS if value_count == 1 => sub_ifds.push(value),
+                    TAG_SUB_IFDS => {
+                        let position = self.inner.stream_position()?;
+                        self.inner.seek(SeekFrom::Start(value as u64))?;
+                        for _ in 0..value_count.min(MAX_IFDS as u32) {
+                            sub_ifds.push(self.read_u32()?);
+                        }
+                        self.inner.seek(SeekFrom::Start(position))?;
+                    }
+                    _ => {}
+                }
+            }
+
+            if let (Some(offset), Some(length)) = (jpeg_offset, jpeg_length) {
+                previews.push(Preview {
+                    offset: offset as u64,
+                    length: length as u64,
+                });
+            }
+            // old-style (6) and new-style (7) JPEG strips; lossless raw data
+            // also uses 7 but is weeded out when the candidate fails to decode
+            if let (Some(6 | 7), Some(offset), Some(length)) =
+                (compression, strip_offset, strip_length)
+            {
+                previews.push(Preview {
+                    offset: offset as u64,
+                    length: length as u64,
+                });
+            }
+
+            pending.extend(sub_ifds);
+            pending.push(next_ifd);
+        }
+
+        Ok(previews)
+    }
+
+    fn read_preview(&mut self, preview: &Preview) -> io::Result<Vec<u8>> {
+        self.inner.seek(SeekFrom::Start(preview.offset))?;
+        let mut data = vec![0u8; preview.length as usize];
+        self.inner.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Embedded JPEG previews of a TIFF based RAW container, largest first
+fn read_previews<R: Read + Seek>(inner: R) -> io::Result<Vec<Vec<u8>>> {
+    let (mut reader, first_ifd) = TiffReader::new(inner)?;
+    let file_length = reader.inner.seek(SeekFrom::End(0))?;
+    let mut previews = reader.previews(first_ifd)?;
+    previews.retain(|p| p.length > 0 && p.offset + p.length <= file_length);
+    previews.sort_by_key(|p| std::cmp::Reverse(p.length));
+    previews.dedup();
+
+    Ok(previews
+        .iter()
+        .filter_map(|p| reader.read_preview(p).ok())
+        .filter(|data| data.starts_with(&[0xFF, 0xD8]))
+        .collect())
+}
+
+/// Bytes of the largest decodable embedded JPEG preview
+pub fn read_preview(path: &Path) -> io::Result<Vec<u8>> {
+    let file = BufReader::new(File::open(path)?);
+
+    read_previews(file)?
+        .into_iter()
+        .find(|data| image::load_from_memory_with_format(data, ImageFormat::Jpeg).is_ok())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no embedded JPEG preview"))
+}
+
+/// Decode the largest embedded JPEG preview of a RAW file
+pub fn load_preview(path: &Path) -> Result<DynamicImage, ImgError> {
+    let file = BufReader::new(File::open(path)?);
+
+    for data in read_previews(file)? {
+        match image::load_from_memory_with_format(&data, ImageFormat::Jpeg) {
+            Ok(img) => return Ok(img),
+            Err(e) => trace!("Skipping undecodable preview in {:?}: {:?}", path, e),
+        }
+    }
+
+    Err(ImgError::IoError(io::Error::new(
+        io::ErrorKind::NotFound,
+        "no embedded JPEG preview",
+    )))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use image::{ImageOutputFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(vec![]);
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut data, ImageOutputFormat::Jpeg(80))
+            .unwrap();
+        data.into_inner()
+    }
+
+    fn entry(tag: u16, field_type: u16, count: u32, value: u32) -> Vec<u8> {
+        let mut entry = vec![];
+        entry.extend(tag.to_le_bytes());
+        entry.extend(field_type.to_le_bytes());
+        entry.extend(count.to_le_bytes());
+        entry.extend(value.to_le_bytes());
+        entry
+    }
+
+    /// A little endian TIFF with a small JPEG thumbnail in IFD0 and a larger
+    /// JPEG strip in a SubIFD, the way NEF and DNG files lay them out
+    fn raw_container(small: &[u8], large: &[u8]) -> Vec<u8> {
+        let ifd0 = 8u32;
+        let sub_ifd = ifd0 + 2 + 3 * 12 + 4;
+        let small_offset = sub_ifd + 2 + 3 * 12 + 4;
+        let large_offset = small_offset + small.len() as u32;
+
+        let mut data = b"II*\0".to_vec();
+        data.extend(ifd0.to_le_bytes());
+
+        data.extend(3u16.to_le_bytes());
+        data.extend(entry(TAG_SUB_IFDS, 4, 1, sub_ifd));
+        data.extend(entry(TAG_JPEG_OFFSET, 4, 1, small_offset));
+        data.extend(entry(TAG_JPEG_LENGTH, 4, 1, small.len() as u32));
+        data.extend(0u32.to_le_bytes());
+
+        data.extend(3u16.to_le_bytes());
+        data.extend(entry(TAG_COMPRESSION, 3, 1, 6));
+        data.extend(entry(TAG_STRIP_OFFSETS, 4, 1, large_offset));
+        data.extend(entry(TAG_STRIP_BYTE_COUNTS, 4, 1, large.len() as u32));
+        data.extend(0u32.to_le_bytes());
+
+        data.extend(small);
+        data.extend(large);
+        data
+    }
+
+    #[test]
+    pub fn it_finds_previews_largest_first() {
+        let small = jpeg(16, 8);
+        let large = jpeg(64, 32);
+        let previews = read_previews(Cursor::new(raw_container(&small, &large))).unwrap();
+        assert_eq!(previews, vec![large, small]);
+    }
+
+    #[test]
+    pub fn it_decodes_the_largest_preview() {
+        let container = raw_container(&jpeg(16, 8), &jpeg(64, 32));
+        let previews = read_previews(Cursor::new(container)).unwrap();
+        let img = image::load_from_memory_with_format(&previews[0], ImageFormat::Jpeg).unwrap();
+        assert_eq!(img.width(), 64);
+    }
+
+    #[test]
+    pub fn it_rejects_non_tiff_files() {
+        let result = read_previews(Cursor::new(jpeg(8, 8)));
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,285 @@
+use crate::caption::CaptionCache;
+use crate::folder::FolderSvc;
+use crate::get_env_typed;
+use crate::image::ImageSvc;
+use crate::pgp::AuthName;
+use crate::SearchHit;
+use lazy_static::lazy_static;
+use log::*;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+lazy_static! {
+    /// Seconds between rebuilds, which is how long new or removed photos take
+    /// to show up in searches
+    static ref SEARCH_REFRESH: u64 = get_env_typed("SEARCH_REFRESH", 300u64).max(10);
+}
+
+/// The most hits one search returns
+pub const MAX_HITS: usize = 100;
+
+/// Contents of the `.hide` files of a folder and every folder above it; all
+/// of them must let a visitor in for them to see what is inside
+type Guards = Arc<Vec<String>>;
+
+struct Entry {
+    hit: SearchHit,
+    /// Lowercased path and text, which every search term must be found in
+    haystack: String,
+    /// Lowercased last path segment, so hits on a name rank above hits on text
+    name: String,
+    guards: Guards,
+}
+
+impl Entry {
+    fn new(hit: SearchHit, guards: Guards) -> Self {
+        let name = hit
+            .path
+            .rsplit('/')
+            .next()
+            .unwrap_or(&hit.path)
+            .to_lowercase();
+        let haystack =
+            format!("{}\n{}", hit.path, hit.text.as_deref().unwrap_or_default()).to_lowercase();
+        Self {
+            hit,
+            haystack,
+            name,
+            guards,
+        }
+    }
+
+    fn visible(&self, auth_type: &Option<AuthName>) -> bool {
+        self.guards
+            .iter()
+            .all(|guard| ImageSvc::hide_allows(guard, auth_type))
+    }
+}
+
+/// Folder paths, file names, `index.txt` texts and captions of the whole
+/// library, rebuilt from disk in the background every `SEARCH_REFRESH` seconds
+pub struct SearchIndex {
+    entries: RwLock<Vec<Entry>>,
+    captions: Arc<CaptionCache>,
+}
+
+/// Whether `folder` and every folder above it let `auth_type` in, going by
+/// the `.hide` files on disk now rather than when the index was built
+pub async fn folder_visible(folder: &str, auth_type: &Option<AuthName>) -> bool {
+    for path in ancestors(folder) {
+        if ImageSvc::is_hidden(&path, auth_type).await {
+            return false;
+        }
+    }
+    true
+}
+
+/// `/`, then each folder down to and including `folder`
+fn ancestors(folder: &str) -> Vec<String> {
+    let mut ancestors = vec!["/".to_string()];
+    let mut path = String::new();
+    for segment in folder.split('/').filter(|s| !s.is_empty()) {
+        path = format!("{path}/{segment}");
+        ancestors.push(path.clone());
+    }
+    ancestors
+}
+
+fn terms(query: &str) -> Vec<String> {
+    query
+        .split_whitespace()
+        .map(|term| term.to_lowercase())
+        .collect()
+}
+
+impl SearchIndex {
+    /// An empty index, reading captions through `captions` so a rebuild only
+    /// reads the ones that changed
+    pub fn new(captions: Arc<CaptionCache>) -> Self {
+        Self {
+            entries: RwLock::default(),
+            captions,
+        }
+    }
+
+    /// Build the index now and keep rebuilding it for as long as the server runs
+    pub fn start(self: &Arc<Self>) {
+        let index = self.clone();
+        tokio::spawn(async move {
+            loop {
+                index.rebuild().await;
+                tokio::time::sleep(Duration::from_secs(*SEARCH_REFRESH)).await;
+            }
+        });
+    }
+
+    pub async fn rebuild(&self) {
+        let entries = self.build().await;
+        info!("Search index rebuilt with {} entries", entries.len());
+        *self.entries.write().unwrap() = entries;
+    }
+
+    /// Hits containing every word of `query`, leaving out anything in a folder
+    /// `auth_type` may not see
+    pub fn search(&self, query: &str, auth_type: &Option<AuthName>) -> Vec<SearchHit> {
+        let terms = terms(query);
+        if terms.is_empty() {
+            return Vec::new();
+        }
+
+        let entries = self.entries.read().unwrap();
+        let mut hits: Vec<(usize, &Entry)> = entries
+            .iter()
+            .filter(|entry| terms.iter().all(|term| entry.haystack.contains(term)))
+            .filter(|entry| entry.visible(auth_type))
+            .map(|entry| {
+                let on_name = terms.iter().filter(|term| entry.name.contains(*term));
+                (on_name.count(), entry)
+            })
+            .collect();
+        hits.sort_by(|(a_score, a), (b_score, b)| {
+            b_score
+                .cmp(a_score)
+                .then_with(|| b.hit.is_folder.cmp(&a.hit.is_folder))
+                .then_with(|| a.hit.path.cmp(&b.hit.path))
+        });
+        hits.into_iter()
+            .take(MAX_HITS)
+            .map(|(_, entry)| entry.hit.clone())
+            .collect()
+    }
+
+    /// Walk `PHOTO_DIR` as `super`, remembering the `.hide` files on the way
+    /// so searches can be filtered per visitor
+    async fn build(&self) -> Vec<Entry> {
+        let admin = Some(AuthName::new("super"));
+        let mut entries = Vec::new();
+        let mut pending = vec![("/".to_string(), Arc::new(Self::guards(&[], "/").await))];
+        while let Some((folder, guards)) = pending.pop() {
+            match FolderSvc::list_internal(&folder, admin.clone()).await {
+                Ok(children) => {
+                    for child in children {
+                        let child_guards = Arc::new(Self::guards(&guards, &child.path).await);
+                        let hit = SearchHit {
+                            path: child.path.clone(),
+                            folder: child.path.clone(),
+                            is_folder: true,
+                            text: child.text,
+                            position: None,
+                        };
+                        entries.push(Entry::new(hit, child_guards.clone()));
+                        pending.push((child.path, child_guards));
+                    }
+                }
+                Err(e) => warn!("Could not index folders of {folder}: {e}"),
+            }
+            match ImageSvc::list_internal(&folder, &admin).await {
+                Ok(images) => {
+                    let images = ImageSvc::attach_captions(&self.captions, images).await;
+                    entries.extend(images.into_iter().map(|image| {
+                        let hit = SearchHit {
+                            path: image.path,
+                            folder: folder.clone(),
+                            is_folder: false,
+                            text: image.caption,
+                            position: None,
+                        };
+                        Entry::new(hit, guards.clone())
+                    }))
+                }
+                Err(e) => warn!("Could not index images of {folder}: {e}"),
+            }
+        }
+        entries
+    }
+
+    /// `parent`'s guards, plus the `.hide` file of `folder` if it has one
+    async fn guards(parent: &[String], folder: &str) -> Vec<String> {
+        let hide_path = format!("{}{}/.hide", crate::base_folder(), folder).replace("//", "/");
+        let mut guards = parent.to_vec();
+        if let Ok(contents) = tokio::fs::read_to_string(hide_path).await {
+            guards.push(contents);
+        }
+        guards
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    fn index(entries: Vec<Entry>) -> SearchIndex {
+        SearchIndex {
+            entries: RwLock::new(entries),
+            captions: Arc::default(),
+        }
+    }
+
+    fn entry(path: &str, is_folder: bool, text: Option<&str>, guards: &[&str]) -> Entry {
+        let hit = SearchHit {
+            path: path.to_string(),
+            folder: path
+                .rsplit_once('/')
+                .map_or("/", |(folder, _)| folder)
+                .to_string(),
+            is_folder,
+            text: text.map(str::to_string),
+            position: None,
+        };
+        Entry::new(
+            hit,
+            Arc::new(guards.iter().map(|g| g.to_string()).collect()),
+        )
+    }
+
+    fn paths(hits: Vec<SearchHit>) -> Vec<String> {
+        hits.into_iter().map(|hit| hit.path).collect()
+    }
+
+    #[test]
+    pub fn it_matches_every_term_in_names_and_text() {
+        let index = index(vec![
+            entry("/Pets", true, Some("Our cats and dogs"), &[]),
+            entry("/Pets/cat.jpg", false, None, &[]),
+            entry("/Holidays/beach.jpg", false, None, &[]),
+        ]);
+        assert_eq!(
+            paths(index.search("CAT", &None)),
+            ["/Pets/cat.jpg", "/Pets"]
+        );
+        assert_eq!(paths(index.search("pets dogs", &None)), ["/Pets"]);
+        assert!(index.search("  ", &None).is_empty());
+    }
+
+    #[test]
+    pub fn it_hides_what_the_visitor_may_not_see() {
+        let index = index(vec![
+            entry("/Private", true, None, &["alice\nbob"]),
+            entry("/Private/party.jpg", false, None, &["alice\nbob"]),
+            entry(
+                "/Private/Inner/party.jpg",
+                false,
+                None,
+                &["alice\nbob", "carol"],
+            ),
+        ]);
+        assert!(index.search("party", &None).is_empty());
+        assert_eq!(
+            paths(index.search("party", &Some(AuthName::new("bob")))),
+            ["/Private/party.jpg"]
+        );
+        assert_eq!(
+            index.search("party", &Some(AuthName::new("super"))).len(),
+            2
+        );
+    }
+
+    #[test]
+    pub fn it_checks_every_folder_above_a_hit() {
+        assert_eq!(
+            ancestors("/Private/Inner/"),
+            ["/", "/Private", "/Private/Inner"]
+        );
+        assert_eq!(ancestors("/"), ["/"]);
+    }
+}
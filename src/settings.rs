@@ -0,0 +1,102 @@
+use crate::base_folder;
+use crate::sort::SortOrder;
+use log::*;
+use std::path::Path;
+use std::str::FromStr;
+use tokio::fs::read_to_string;
+
+/// Per-folder overrides read from a `.settings` file next to the photos,
+/// one `key = value` pair per line:
+///
+/// ```text
+/// # newest first
+/// sort = -captured
+/// downloads = false
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FolderSettings {
+    pub sort: Option<SortOrder>,
+    /// Whether full-resolution originals may be downloaded; thumbnails are unaffected
+    pub downloads: bool,
+}
+
+impl Default for FolderSettings {
+    fn default() -> Self {
+        Self {
+            sort: None,
+            downloads: true,
+        }
+    }
+}
+
+impl FolderSettings {
+    pub async fn load(folder: &str) -> Self {
+        let settings_path = format!("{}{}/.settings", base_folder(), folder).replace("//", "/");
+        let settings_path = Path::new(settings_path.as_str());
+        match read_to_string(settings_path).await {
+            Ok(contents) => Self::parse(&contents),
+            Err(_) => Self::default(),
+        }
+    }
+
+    fn parse(contents: &str) -> Self {
+        let mut settings = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                warn!("Ignoring malformed folder setting '{line}'");
+                continue;
+            };
+            let (key, value) = (key.trim(), value.trim());
+            match key {
+                "sort" => settings.sort = SortOrder::from_str(value).ok(),
+                "downloads" => settings.downloads = parse_flag(key, value),
+                _ => warn!("Ignoring unknown folder setting '{key}'"),
+            }
+        }
+
+        settings
+    }
+}
+
+/// `true`/`yes`/`1` or `false`/`no`/`0`; anything else turns the setting off,
+/// since the flags restrict access
+fn parse_flag(key: &str, value: &str) -> bool {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "1" => true,
+        "false" | "no" | "0" => false,
+        _ => {
+            warn!("Unreadable folder setting '{key} = {value}', treating it as false");
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_parses_settings() {
+        let settings =
+            FolderSettings::parse("# comment\n\nsort = -captured\nbogus\ndownloads = false\n");
+        assert_eq!(settings.sort, Some(SortOrder::CapturedDesc));
+        assert!(!settings.downloads);
+    }
+
+    #[test]
+    pub fn it_defaults_without_settings() {
+        assert_eq!(FolderSettings::parse(""), FolderSettings::default());
+        assert!(FolderSettings::default().downloads);
+    }
+
+    #[test]
+    pub fn it_fails_closed_on_unreadable_flags() {
+        for (value, downloads) in [("YES", true), ("1", true), ("no", false), ("off", false)] {
+            let settings = FolderSettings::parse(&format!("downloads = {value}"));
+            assert_eq!(settings.downloads, downloads, "downloads = {value}");
+        }
+    }
+}
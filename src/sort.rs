@@ -0,0 +1,216 @@
+use crate::Image;
+use std::cmp::Ordering;
+use std::fmt;
+use std::str::FromStr;
+
+/// Order of images within a folder; a leading `-` reverses it (`-captured`)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SortOrder {
+    #[default]
+    Name,
+    NameDesc,
+    Captured,
+    CapturedDesc,
+    Modified,
+    ModifiedDesc,
+}
+
+impl FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "name" => Ok(SortOrder::Name),
+            "-name" => Ok(SortOrder::NameDesc),
+            "captured" => Ok(SortOrder::Captured),
+            "-captured" => Ok(SortOrder::CapturedDesc),
+            "mtime" => Ok(SortOrder::Modified),
+            "-mtime" => Ok(SortOrder::ModifiedDesc),
+            other => anyhow::bail!("unknown sort order '{other}'"),
+        }
+    }
+}
+
+impl fmt::Display for SortOrder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SortOrder::Name => write!(f, "name"),
+            SortOrder::NameDesc => write!(f, "-name"),
+            SortOrder::Captured => write!(f, "captured"),
+            SortOrder::CapturedDesc => write!(f, "-captured"),
+            SortOrder::Modified => write!(f, "mtime"),
+            SortOrder::ModifiedDesc => write!(f, "-mtime"),
+        }
+    }
+}
+
+impl SortOrder {
+    pub fn sort(&self, images: &mut [Image]) {
+        match self {
+            SortOrder::Name => images.sort_by(by_name),
+            SortOrder::NameDesc => images.sort_by(|a, b| by_name(b, a)),
+            SortOrder::Captured => images.sort_by(|a, b| by_capture(a, b, false)),
+            SortOrder::CapturedDesc => images.sort_by(|a, b| by_capture(a, b, true)),
+            SortOrder::Modified => images.sort_by(by_modified),
+            SortOrder::ModifiedDesc => images.sort_by(|a, b| by_modified(b, a)),
+        }
+    }
+}
+
+fn by_name(a: &Image, b: &Image) -> Ordering {
+    natural_cmp(file_name(&a.path), file_name(&b.path))
+}
+
+/// Images without a capture time sort after dated ones, whichever way the
+/// dated ones run
+fn by_capture(a: &Image, b: &Image, descending: bool) -> Ordering {
+    let captured = |i: &Image| i.exif.as_ref().and_then(|e| e.captured_at.clone());
+    match (captured(a), captured(b)) {
+        (Some(x), Some(y)) => {
+            let order = x.cmp(&y).then_with(|| by_name(a, b));
+            if descending {
+                order.reverse()
+            } else {
+                order
+            }
+        }
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => by_name(a, b),
+    }
+}
+
+fn by_modified(a: &Image, b: &Image) -> Ordering {
+    a.modified.cmp(&b.modified).then_with(|| by_name(a, b))
+}
+
+fn file_name(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+/// Compare strings so that runs of digits compare by value: `IMG_2` < `IMG_10`
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x = take_number(&mut a);
+                let y = take_number(&mut b);
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Consume a run of digits, dropping leading zeros
+fn take_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        if !(digits.is_empty() && c == '0') {
+            digits.push(c);
+        }
+    }
+    digits
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use crate::ExifData;
+
+    fn image(path: &str, captured_at: Option<&str>, modified: u64) -> Image {
+        Image {
+            exif: Some(ExifData {
+                captured_at: captured_at.map(str::to_string),
+                ..Default::default()
+            }),
+            modified: Some(modified),
+            ..Image::new(path.to_string())
+        }
+    }
+
+    fn paths(images: &[Image]) -> Vec<&str> {
+        images.iter().map(|i| i.path.as_str()).collect()
+    }
+
+    #[test]
+    pub fn it_compares_naturally() {
+        assert_eq!(natural_cmp("IMG_2.jpg", "IMG_10.jpg"), Ordering::Less);
+        assert_eq!(natural_cmp("IMG_010.jpg", "IMG_9.jpg"), Ordering::Greater);
+        assert_eq!(natural_cmp("img_1.jpg", "IMG_1.jpg"), Ordering::Equal);
+        assert_eq!(natural_cmp("a", "a1"), Ordering::Less);
+    }
+
+    #[test]
+    pub fn it_sorts_by_name() {
+        let mut images = vec![
+            image("/a/IMG_10.jpg", None, 0),
+            image("/a/IMG_2.jpg", None, 0),
+        ];
+        SortOrder::Name.sort(&mut images);
+        assert_eq!(paths(&images), vec!["/a/IMG_2.jpg", "/a/IMG_10.jpg"]);
+        SortOrder::NameDesc.sort(&mut images);
+        assert_eq!(paths(&images), vec!["/a/IMG_10.jpg", "/a/IMG_2.jpg"]);
+    }
+
+    #[test]
+    pub fn it_sorts_by_capture_time() {
+        let mut images = vec![
+            image("/a/DSC_1.jpg", Some("2024-05-01T12:00:05"), 0),
+            image("/a/undated.jpg", None, 0),
+            image("/a/IMG_9.jpg", Some("2024-05-01T12:00:01"), 0),
+        ];
+        SortOrder::Captured.sort(&mut images);
+        assert_eq!(
+            paths(&images),
+            vec!["/a/IMG_9.jpg", "/a/DSC_1.jpg", "/a/undated.jpg"]
+        );
+    }
+
+    #[test]
+    pub fn it_keeps_undated_images_last_when_descending() {
+        let mut images = vec![
+            image("/a/undated.jpg", None, 0),
+            image("/a/IMG_9.jpg", Some("2024-05-01T12:00:01"), 0),
+            image("/a/DSC_1.jpg", Some("2024-05-01T12:00:05"), 0),
+        ];
+        SortOrder::CapturedDesc.sort(&mut images);
+        assert_eq!(
+            paths(&images),
+            vec!["/a/DSC_1.jpg", "/a/IMG_9.jpg", "/a/undated.jpg"]
+        );
+    }
+
+    #[test]
+    pub fn it_sorts_by_mtime() {
+        let mut images = vec![image("/a/1.jpg", None, 20), image("/a/2.jpg", None, 10)];
+        SortOrder::Modified.sort(&mut images);
+        assert_eq!(paths(&images), vec!["/a/2.jpg", "/a/1.jpg"]);
+        SortOrder::ModifiedDesc.sort(&mut images);
+        assert_eq!(paths(&images), vec!["/a/1.jpg", "/a/2.jpg"]);
+    }
+
+    #[test]
+    pub fn it_parses_sort_orders() {
+        for order in ["name", "-name", "captured", "-captured", "mtime", "-mtime"] {
+            assert_eq!(SortOrder::from_str(order).unwrap().to_string(), order);
+        }
+        assert!(SortOrder::from_str("size").is_err());
+    }
+}
@@ -0,0 +1,678 @@
+use crate::image::ImageError;
+use crate::{base_folder, get_env};
+use futures::future::{BoxFuture, FutureExt, Shared};
+use lazy_static::lazy_static;
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DEFAULT_SIZES: &str = "150,300,600,1200,2400";
+
+lazy_static! {
+    static ref THUMB_SIZES: Vec<u32> = parse_sizes(&get_env("THUMB_SIZES", DEFAULT_SIZES));
+    static ref SNAP_SIZES: bool =
+        !get_env("THUMB_SIZE_POLICY", "snap").eq_ignore_ascii_case("reject");
+    static ref IN_FLIGHT: SingleFlight = SingleFlight::default();
+    static ref GENERATING: SharedFlight<Result<(), ImageError>> = SharedFlight::default();
+}
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Suffix of partially written thumbnails
+pub const TEMP_SUFFIX: &str = ".tmp";
+
+/// Placeholder hashes are stored beside the thumbnails and named like one
+/// rendered at this size, so pruning covers them too
+pub const PLACEHOLDER_SIZE: u32 = 32;
+pub const PLACEHOLDER_EXTENSION: &str = "blurhash";
+
+// Temp files older than this were left behind by a crash
+const TEMP_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Sorted, de-duplicated sizes from a comma separated list; falls back to
+/// the defaults when nothing usable is configured
+fn parse_sizes(sizes: &str) -> Vec<u32> {
+    let mut parsed: Vec<u32> = sizes
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .filter_map(|s| match s.parse::<u32>() {
+            Ok(size) if size > 0 => Some(size),
+            _ => {
+                warn!("Ignoring invalid thumbnail size '{s}'");
+                None
+            }
+        })
+        .collect();
+    parsed.sort_unstable();
+    parsed.dedup();
+
+    if parsed.is_empty() && sizes != DEFAULT_SIZES {
+        return parse_sizes(DEFAULT_SIZES);
+    }
+    parsed
+}
+
+/// Thumbnail edge lengths the server will generate, smallest first
+pub fn thumb_sizes() -> Vec<u32> {
+    THUMB_SIZES.clone()
+}
+
+/// The closest allowed size, preferring the larger one on a tie
+fn nearest_size(sizes: &[u32], requested: u32) -> Option<u32> {
+    sizes
+        .iter()
+        .copied()
+        .min_by_key(|size| (size.abs_diff(requested), u32::MAX - size))
+}
+
+/// Map a requested size onto the allow-list: exact matches pass through, and
+/// anything else snaps to the nearest allowed size or, with
+/// `THUMB_SIZE_POLICY=reject`, is refused
+pub fn resolve_size(requested: u32) -> Option<u32> {
+    if THUMB_SIZES.contains(&requested) {
+        Some(requested)
+    } else if *SNAP_SIZES {
+        nearest_size(&THUMB_SIZES, requested)
+    } else {
+        None
+    }
+}
+
+/// Cache root from `THUMB_DIR`, without a trailing slash. Unset keeps the old
+/// layout of `.thumbs` directories inside the library.
+pub fn thumb_dir() -> Option<String> {
+    let dir = get_env("THUMB_DIR", "");
+    let dir = dir.trim_end_matches('/');
+    if dir.is_empty() {
+        None
+    } else {
+        Some(dir.to_string())
+    }
+}
+
+/// Directory holding the thumbnails of images in `folder`, which is relative
+/// to `PHOTO_DIR`
+pub fn thumb_folder(folder: &str) -> String {
+    let folder = folder.trim_matches('/');
+    match thumb_dir() {
+        Some(root) if folder.is_empty() => root,
+        Some(root) => format!("{}/{}", root, folder),
+        None => {
+            let base_folder = base_folder();
+            let base_folder = base_folder.strip_suffix('/').unwrap_or(&base_folder);
+            if folder.is_empty() {
+                format!("{}/.thumbs", base_folder)
+            } else {
+                format!("{}/{}/.thumbs", base_folder, folder)
+            }
+        }
+    }
+}
+
+/// Directory holding the `thumb-<size>` images representing `folder`
+pub fn folder_thumb_folder(folder: &str) -> String {
+    match thumb_dir() {
+        Some(root) => format!("{}/{}", root, folder.trim_matches('/')),
+        None => format!("{}{}", base_folder(), folder),
+    }
+}
+
+/// One lock per key, so only one caller at a time does the work for a key
+/// while the others wait for it and then find the result on disk
+#[derive(Default)]
+pub struct SingleFlight {
+    locks: Mutex<HashMap<String, Arc<Mutex<()>>>>,
+}
+
+impl SingleFlight {
+    /// Run `f` while holding the lock for `key`; blocks, so call it from a
+    /// blocking task
+    pub fn run<T>(&self, key: &str, f: impl FnOnce() -> T) -> T {
+        let lock = self
+            .locks
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_default()
+            .clone();
+        let result = {
+            let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+            f()
+        };
+
+        let mut locks = self.locks.lock().unwrap();
+        // only the map and this caller still hold it
+        if Arc::strong_count(&lock) == 2 {
+            locks.remove(key);
+        }
+        result
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.locks.lock().unwrap().len()
+    }
+}
+
+/// Generate the thumbnail at `thumb_filename` with `f` unless another caller
+/// already has, or is in the middle of doing so
+pub fn single_flight<T>(thumb_filename: &str, f: impl FnOnce() -> T) -> T {
+    IN_FLIGHT.run(thumb_filename, f)
+}
+
+/// One future per key, shared by every caller asking for the same key while
+/// it runs. Unlike `SingleFlight`, waiters hold no thread, so duplicates can
+/// be folded together before anything is queued.
+pub struct SharedFlight<T: Clone> {
+    running: Mutex<HashMap<String, Shared<BoxFuture<'static, T>>>>,
+}
+
+impl<T: Clone> Default for SharedFlight<T> {
+    fn default() -> Self {
+        Self {
+            running: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<T: Clone + Send + Sync + 'static> SharedFlight<T> {
+    /// Await the running future for `key`, or start one with `f`
+    pub async fn run<F>(&self, key: &str, f: impl FnOnce() -> F) -> T
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let flight = self
+            .running
+            .lock()
+            .unwrap()
+            .entry(key.to_string())
+            .or_insert_with(|| f().boxed().shared())
+            .clone();
+        let result = flight.clone().await;
+
+        let mut running = self.running.lock().unwrap();
+        // a later flight may already have taken this one's place
+        if running
+            .get(key)
+            .is_some_and(|current| Shared::ptr_eq(current, &flight))
+        {
+            running.remove(key);
+        }
+        result
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.running.lock().unwrap().len()
+    }
+}
+
+/// Generate a thumbnail with `f` unless a request for the same `key` is
+/// already doing so, in which case wait for that one instead
+pub async fn coalesce<F>(key: &str, f: impl FnOnce() -> F) -> Result<(), ImageError>
+where
+    F: Future<Output = Result<(), ImageError>> + Send + 'static,
+{
+    GENERATING.run(key, f).await
+}
+
+/// Write `data` next to `path` and rename it into place, so readers never see
+/// a partial file. Missing parent directories are created.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(
+        ".{}-{}{}",
+        std::process::id(),
+        TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+        TEMP_SUFFIX
+    ));
+    let temp = PathBuf::from(temp);
+
+    let result = std::fs::write(&temp, data).and_then(|_| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Short fingerprint of an original's modification time and size; changes
+/// whenever the file is rewritten
+pub fn source_identity(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_nanos();
+
+    // FNV-1a, so names stay stable across builds
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in modified
+        .to_le_bytes()
+        .iter()
+        .chain(metadata.len().to_le_bytes().iter())
+    {
+        hash ^= u32::from(*byte);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    Some(format!("{:08x}", hash))
+}
+
+/// The parts of a thumbnail name, `<source>-<size>[-o<orientation>][-<identity>].<ext>`
+#[derive(Debug, PartialEq)]
+pub struct ThumbName<'a> {
+    pub source: &'a str,
+    pub size: u32,
+    pub identity: Option<&'a str>,
+    pub extension: &'a str,
+}
+
+pub fn parse_thumb_name(name: &str) -> Option<ThumbName<'_>> {
+    let (mut stem, extension) = name.rsplit_once('.')?;
+
+    let mut identity = None;
+    if let Some((rest, id)) = stem.rsplit_once('-') {
+        if id.len() == 8 && id.chars().all(|c| c.is_ascii_hexdigit()) {
+            identity = Some(id);
+            stem = rest;
+        }
+    }
+    if let Some((rest, orientation)) = stem.rsplit_once('-') {
+        if orientation
+            .strip_prefix('o')
+            .is_some_and(|o| o.parse::<u32>().is_ok())
+        {
+            stem = rest;
+        }
+    }
+    let (source, size) = stem.rsplit_once('-')?;
+    let size = size.parse().ok()?;
+
+    Some(ThumbName {
+        source,
+        size,
+        identity,
+        extension,
+    })
+}
+
+/// Counts from `prune`
+#[derive(Debug, Default, PartialEq)]
+pub struct Prune {
+    pub kept: usize,
+    pub removed: usize,
+}
+
+/// Every (thumbnail directory, library folder it belongs to) pair
+fn thumb_dirs(library: &Path, thumb_root: Option<&Path>) -> Vec<(PathBuf, PathBuf)> {
+    let mut dirs = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let folder = match thumb_root {
+            Some(root) => root.join(&relative),
+            None => library.join(&relative),
+        };
+        let Ok(entries) = fs::read_dir(&folder) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            if thumb_root.is_none() && entry.file_name() == ".thumbs" {
+                dirs.push((entry.path(), folder.clone()));
+            } else {
+                pending.push(relative.join(entry.file_name()));
+            }
+        }
+        if thumb_root.is_some() {
+            dirs.push((folder, library.join(&relative)));
+        }
+    }
+    dirs
+}
+
+fn is_stale_temp(entry: &fs::DirEntry) -> bool {
+    entry.file_name().to_string_lossy().ends_with(TEMP_SUFFIX)
+        && entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|m| SystemTime::now().duration_since(m).ok())
+            .is_some_and(|age| age > TEMP_MAX_AGE)
+}
+
+fn prune_dir(thumbs: &Path, library_folder: &Path, prune: &mut Prune) {
+    let Ok(entries) = fs::read_dir(thumbs) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if !entry.file_type().is_ok_and(|t| t.is_file()) || is_folder_thumb(&name) {
+            continue;
+        }
+
+        let remove = if name.ends_with(TEMP_SUFFIX) {
+            is_stale_temp(&entry)
+        } else if let Some(thumb) = parse_thumb_name(&name) {
+            let source = library_folder.join(thumb.source);
+            // removed, renamed or re-exported since the thumbnail was made
+            !source.is_file() || thumb.identity != source_identity(&source).as_deref()
+        } else {
+            false
+        };
+
+        if !remove {
+            prune.kept += 1;
+        } else if let Err(e) = fs::remove_file(entry.path()) {
+            error!("Could not prune {:?}: {:?}", entry.path(), e);
+        } else {
+            trace!("Pruned {:?}", entry.path());
+            prune.removed += 1;
+        }
+    }
+
+    // only succeeds once nothing is left in it
+    if !library_folder.is_dir() {
+        let _ = fs::remove_dir(thumbs);
+    }
+}
+
+/// Delete thumbnails whose originals were removed, renamed or changed
+pub fn prune(library: &Path, thumb_root: Option<&Path>) -> Prune {
+    let mut prune = Prune::default();
+    for (thumbs, library_folder) in thumb_dirs(library, thumb_root) {
+        prune_dir(&thumbs, &library_folder, &mut prune);
+    }
+    prune
+}
+
+/// `prune` over `PHOTO_DIR` and the configured thumbnail location
+pub fn prune_thumbnails() -> Prune {
+    let thumb_root = thumb_dir();
+    prune(
+        Path::new(&base_folder()),
+        thumb_root.as_deref().map(Path::new),
+    )
+}
+
+/// Counts from `migrate`
+#[derive(Debug, Default, PartialEq)]
+pub struct Migration {
+    pub moved: usize,
+    pub skipped: usize,
+    pub failed: usize,
+}
+
+/// `thumb-300` or `thumb-300.avif`
+fn is_folder_thumb(name: &str) -> bool {
+    let Some(rest) = name.strip_prefix("thumb-") else {
+        return false;
+    };
+    let size = rest.split_once('.').map_or(rest, |(size, _)| size);
+    !size.is_empty() && size.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Rename, falling back to copy and delete across filesystems
+fn move_file(from: &Path, to: &Path) -> io::Result<()> {
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    fs::remove_file(from)
+}
+
+fn migrate_file(from: &Path, to: &Path, migration: &mut Migration) {
+    if to.exists() {
+        trace!("Already migrated: {:?}", to);
+        migration.skipped += 1;
+        let _ = fs::remove_file(from);
+        return;
+    }
+    match move_file(from, to) {
+        Ok(_) => migration.moved += 1,
+        Err(e) => {
+            error!("Could not move {:?} to {:?}: {:?}", from, to, e);
+            migration.failed += 1;
+        }
+    }
+}
+
+/// Move the `.thumbs` directories and `thumb-<size>` files found below
+/// `library` into the same relative place under `thumb_dir`
+pub fn migrate(library: &Path, thumb_dir: &Path) -> io::Result<Migration> {
+    let mut migration = Migration::default();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let folder = library.join(&relative);
+        for entry in fs::read_dir(&folder)?.flatten() {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            let Ok(file_type) = entry.file_type() else {
+                continue;
+            };
+
+            if file_type.is_dir() && name == ".thumbs" {
+                for thumb in fs::read_dir(entry.path())?.flatten() {
+                    let target = thumb_dir.join(&relative).join(thumb.file_name());
+                    migrate_file(&thumb.path(), &target, &mut migration);
+                }
+                if let Err(e) = fs::remove_dir(entry.path()) {
+                    warn!("Could not remove {:?}: {:?}", entry.path(), e);
+                }
+            } else if file_type.is_dir() {
+                pending.push(relative.join(entry.file_name()));
+            } else if is_folder_thumb(&name) {
+                let target = thumb_dir.join(&relative).join(entry.file_name());
+                migrate_file(&entry.path(), &target, &mut migration);
+            }
+        }
+    }
+
+    Ok(migration)
+}
+
+/// Move thumbnails from inside `PHOTO_DIR` into `THUMB_DIR`
+pub fn migrate_to_thumb_dir() -> io::Result<Migration> {
+    let Some(thumb_dir) = thumb_dir() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "THUMB_DIR is not set",
+        ));
+    };
+    migrate(Path::new(&base_folder()), Path::new(&thumb_dir))
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[test]
+    pub fn it_parses_sizes() {
+        assert_eq!(parse_sizes("600, 150,abc,0,150"), vec![150, 600]);
+        assert_eq!(parse_sizes(""), vec![150, 300, 600, 1200, 2400]);
+    }
+
+    #[test]
+    pub fn it_snaps_to_the_nearest_size() {
+        let sizes = [150, 300, 600];
+        assert_eq!(nearest_size(&sizes, 1), Some(150));
+        assert_eq!(nearest_size(&sizes, 420), Some(300));
+        assert_eq!(nearest_size(&sizes, 450), Some(600));
+        assert_eq!(nearest_size(&sizes, 100000), Some(600));
+        assert_eq!(nearest_size(&[], 300), None);
+    }
+
+    #[test]
+    pub fn it_passes_allowed_sizes_through() {
+        for size in thumb_sizes() {
+            assert_eq!(resolve_size(size), Some(size));
+        }
+    }
+
+    #[test]
+    pub fn it_runs_one_flight_at_a_time() {
+        let flight = Arc::new(SingleFlight::default());
+        let running = Arc::new(AtomicU64::new(0));
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let (flight, running) = (flight.clone(), running.clone());
+                std::thread::spawn(move || {
+                    flight.run("a.jpg-300.webp", || {
+                        assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                        std::thread::sleep(std::time::Duration::from_millis(5));
+                        running.fetch_sub(1, Ordering::SeqCst);
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(flight.len(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn it_shares_one_future_between_callers() {
+        let flight = Arc::new(SharedFlight::<u64>::default());
+        let started = Arc::new(AtomicU64::new(0));
+        let (release, released) = tokio::sync::oneshot::channel::<()>();
+        let released = released.shared();
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let (flight, started, released) =
+                    (flight.clone(), started.clone(), released.clone());
+                tokio::spawn(async move {
+                    flight
+                        .run("a.jpg-300.webp", || async move {
+                            started.fetch_add(1, Ordering::SeqCst);
+                            let _ = released.await;
+                            7
+                        })
+                        .await
+                })
+            })
+            .collect();
+        while flight.len() == 0 {
+            tokio::task::yield_now().await;
+        }
+        release.send(()).unwrap();
+        for caller in callers {
+            assert_eq!(caller.await.unwrap(), 7);
+        }
+        assert_eq!(started.load(Ordering::SeqCst), 1);
+        assert_eq!(flight.len(), 0);
+    }
+
+    #[test]
+    pub fn it_recognises_folder_thumbs() {
+        assert!(is_folder_thumb("thumb-300"));
+        assert!(is_folder_thumb("thumb-300.avif"));
+        assert!(!is_folder_thumb("thumb-large.jpg"));
+        assert!(!is_folder_thumb("IMG_1.jpg"));
+    }
+
+    #[test]
+    pub fn it_migrates_thumbnails_out_of_the_library() {
+        let root = std::env::temp_dir().join(format!("thumb-migrate-{}", std::process::id()));
+        let (library, thumbs) = (root.join("photos"), root.join("thumbs"));
+        fs::create_dir_all(library.join("Pets/.thumbs")).unwrap();
+        fs::create_dir_all(library.join(".thumbs")).unwrap();
+        for file in [
+            "a.jpg",
+            ".thumbs/a.jpg-300.webp",
+            "Pets/b.jpg",
+            "Pets/thumb-300",
+            "Pets/.thumbs/b.jpg-300.avif",
+        ] {
+            fs::write(library.join(file), file).unwrap();
+        }
+
+        let migration = migrate(&library, &thumbs).unwrap();
+        assert_eq!(migration.moved, 3);
+        assert_eq!(migration.failed, 0);
+        assert!(thumbs.join("a.jpg-300.webp").exists());
+        assert!(thumbs.join("Pets/thumb-300").exists());
+        assert!(thumbs.join("Pets/b.jpg-300.avif").exists());
+        assert!(!library.join("Pets/.thumbs").exists());
+        assert!(!library.join("Pets/thumb-300").exists());
+        assert!(library.join("Pets/b.jpg").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn it_parses_thumb_names() {
+        let name = parse_thumb_name("my-photo.jpg-300-o6-1a2b3c4d.webp").unwrap();
+        assert_eq!(name.source, "my-photo.jpg");
+        assert_eq!(name.size, 300);
+        assert_eq!(name.identity, Some("1a2b3c4d"));
+        assert_eq!(name.extension, "webp");
+
+        let legacy = parse_thumb_name("a.jpg-300.avif").unwrap();
+        assert_eq!((legacy.source, legacy.identity), ("a.jpg", None));
+        assert_eq!(parse_thumb_name("thumb"), None);
+    }
+
+    #[test]
+    pub fn it_prunes_orphaned_and_stale_thumbnails() {
+        let root = std::env::temp_dir().join(format!("thumb-prune-{}", std::process::id()));
+        let (library, thumbs) = (root.join("photos"), root.join("thumbs"));
+        fs::create_dir_all(&library).unwrap();
+        fs::create_dir_all(thumbs.join("Gone")).unwrap();
+        fs::write(library.join("a.jpg"), "a").unwrap();
+        let identity = source_identity(&library.join("a.jpg")).unwrap();
+
+        let current = format!("a.jpg-300-{}.webp", identity);
+        for file in [
+            current.as_str(),
+            "a.jpg-300-00000000.webp",
+            "a.jpg-300.webp",
+            "deleted.jpg-300.webp",
+            "thumb-300",
+            "Gone/b.jpg-300.webp",
+        ] {
+            fs::write(thumbs.join(file), file).unwrap();
+        }
+
+        let pruned = prune(&library, Some(&thumbs));
+        assert_eq!(
+            pruned,
+            Prune {
+                kept: 1,
+                removed: 4
+            }
+        );
+        assert!(thumbs.join(&current).exists());
+        assert!(thumbs.join("thumb-300").exists());
+        assert!(!thumbs.join("Gone").exists());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    pub fn it_writes_atomically() {
+        let dir = std::env::temp_dir().join(format!("thumb-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("a.jpg-300.webp");
+        write_atomic(&path, b"first").unwrap();
+        write_atomic(&path, b"second").unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"second");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,189 @@
+use crate::context::GraphQLContext;
+use crate::folder::FolderSvc;
+use crate::image::{ImageError, ImageSvc};
+use crate::pgp::AuthName;
+use crate::queue::{background_workers, run_background};
+use crate::thumbnail::prune_thumbnails;
+use crate::Image;
+use futures::stream::{self, StreamExt};
+use lazy_static::lazy_static;
+use log::*;
+use serde::Serialize;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    /// Images being pre-generated at once; one per thread background jobs
+    /// may use
+    static ref PREGENERATING: usize = background_workers();
+}
+
+/// Snapshot of the current (or last) pre-generation run
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct WorkerProgress {
+    pub running: bool,
+    pub folders_total: usize,
+    pub folders_done: usize,
+    pub images_pending: usize,
+    pub images_done: usize,
+    pub failures: usize,
+    /// Thumbnails deleted because their original changed or went away
+    pub pruned: usize,
+    /// Seconds since the epoch
+    pub started_at: Option<u64>,
+    pub finished_at: Option<u64>,
+}
+
+/// Walks `PHOTO_DIR` and renders any missing thumbnails, so the first visitor
+/// to a folder does not wait on them, then prunes thumbnails of originals that
+/// changed or are gone
+#[derive(Default)]
+pub struct ThumbnailWorker {
+    progress: Mutex<WorkerProgress>,
+}
+
+fn now() -> Option<u64> {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .map(|d| d.as_secs())
+}
+
+impl ThumbnailWorker {
+    pub fn progress(&self) -> WorkerProgress {
+        self.progress.lock().unwrap().clone()
+    }
+
+    fn update(&self, f: impl FnOnce(&mut WorkerProgress)) {
+        f(&mut self.progress.lock().unwrap());
+    }
+
+    /// Start a run in the background; returns false if one is already going
+    pub fn start(self: &Arc<Self>, context: Arc<GraphQLContext>) -> bool {
+        {
+            let mut progress = self.progress.lock().unwrap();
+            if progress.running {
+                return false;
+            }
+            *progress = WorkerProgress {
+                running: true,
+                started_at: now(),
+                ..Default::default()
+            };
+        }
+
+        let worker = self.clone();
+        tokio::spawn(async move {
+            worker.run(&context).await;
+            worker.update(|p| {
+                p.running = false;
+                p.finished_at = now();
+            });
+            info!("Thumbnail pre-generation finished: {:?}", worker.progress());
+        });
+        true
+    }
+
+    async fn run(&self, context: &GraphQLContext) {
+        // hidden folders get thumbnails too
+        let context = context.attach_session(Some(AuthName::new("super")));
+        let folders = Self::collect(&context).await;
+        self.update(|p| {
+            p.folders_total = folders.len();
+            p.images_pending = folders.iter().map(|(_, images)| images.len()).sum();
+        });
+
+        for (folder, images) in folders {
+            trace!("Pre-generating thumbnails for {folder}");
+            stream::iter(images)
+                .map(|image| Self::pregenerate(image.path))
+                .buffer_unordered(*PREGENERATING)
+                .for_each(|result| async move {
+                    let ok = result.is_ok();
+                    self.update(|p| {
+                        p.images_pending -= 1;
+                        if ok {
+                            p.images_done += 1;
+                        } else {
+                            p.failures += 1;
+                        }
+                    });
+                })
+                .await;
+            self.update(|p| p.folders_done += 1);
+        }
+
+        match tokio::task::spawn_blocking(prune_thumbnails).await {
+            Ok(pruned) => self.update(|p| p.pruned = pruned.removed),
+            Err(e) => error!("Could not prune thumbnails: {:?}", e),
+        }
+    }
+
+    /// Decode an image once, then render each missing thumbnail as its own
+    /// background job, so a visitor never waits behind a whole image's worth
+    async fn pregenerate(path: String) -> Result<(), ImageError> {
+        let filename = path.clone();
+        let Some(pregeneration) =
+            run_background(move || ImageSvc::prepare_pregeneration(&filename)).await??
+        else {
+            return Ok(());
+        };
+        let pregeneration = Arc::new(pregeneration);
+
+        let mut failures = 0;
+        for (size, format) in pregeneration.missing.clone() {
+            let pregeneration = pregeneration.clone();
+            if run_background(move || pregeneration.render(size, format))
+                .await
+                .and_then(|result| result)
+                .is_err()
+            {
+                failures += 1;
+            }
+        }
+        if failures > 0 {
+            return Err(ImageError::ThumbError(format!(
+                "{failures} thumbnails of {path} failed"
+            )));
+        }
+        Ok(())
+    }
+
+    /// Every folder below `PHOTO_DIR` with its images
+    async fn collect(context: &GraphQLContext) -> Vec<(String, Vec<Image>)> {
+        let mut pending = vec!["/".to_string()];
+        let mut folders = Vec::new();
+        while let Some(folder) = pending.pop() {
+            match FolderSvc::list(context, &folder).await {
+                Ok(children) => pending.extend(children.into_iter().map(|f| f.path)),
+                Err(e) => warn!("Could not list folders of {folder}: {e}"),
+            }
+            match ImageSvc::list(context, &folder).await {
+                Ok(images) => folders.push((folder, images)),
+                Err(e) => warn!("Could not list images of {folder}: {e}"),
+            }
+        }
+        folders
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    #[tokio::test]
+    pub async fn it_refuses_overlapping_runs() {
+        dotenvy::from_filename(".env.test").ok();
+        let worker = Arc::new(ThumbnailWorker::default());
+        worker.update(|p| p.running = true);
+        assert!(!worker.start(Arc::new(GraphQLContext::default())));
+        assert_eq!(worker.progress().started_at, None);
+    }
+
+    #[test]
+    pub fn it_starts_idle() {
+        let progress = ThumbnailWorker::default().progress();
+        assert!(!progress.running);
+        assert_eq!(progress.folders_done, 0);
+    }
+}
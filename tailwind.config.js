@@ -8,6 +8,18 @@
       theme: {
         extend: {
 
+        // slideshow transitions
+        keyframes: {
+          'slide-in': { from: { opacity: '0' }, to: { opacity: '1' } },
+          'slide-out': { from: { opacity: '1' }, to: { opacity: '0' } },
+        },
+        animation: {
+          'slide-in': 'slide-in 1s ease-in-out both',
+          'slide-in-late': 'slide-in 0.5s ease-in 0.5s both',
+          'slide-out': 'slide-out 0.5s ease-out both',
+          'slide-out-slow': 'slide-out 1s ease-in-out both',
+        },
+
         colors: {
       'veniceblue': {
     '50': '#f3f7fc',